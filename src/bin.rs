@@ -0,0 +1,171 @@
+use std::{fs::File, io::Write, path::Path};
+
+use crate::{
+    generator::{align_up, gen_program, link_section, parse_file},
+    node::ProgramNode,
+};
+
+// index of a section named by "follows=" or "vfollows="
+fn find_section(program: &ProgramNode, name: &str) -> usize {
+    match program.section_nodes.iter().position(|s| s.name == name) {
+        Some(i) => i,
+        None => panic!("Section \"{}\" is not defined", name),
+    }
+}
+
+// raw image of the sections, like boot sectors
+pub fn gen_bin(input_filepath: &Path, output_filepath: &Path) -> File {
+    let tokens = parse_file(input_filepath);
+    // same as nasm, flat images start in 16-bit mode
+    let program = gen_program(&tokens, input_filepath, 16, false);
+
+    let origin = program.origin.unwrap_or(0);
+    let section_num = program.section_nodes.len();
+
+    // sections in source order, nobits ones after the others
+    let mut order: Vec<usize> = (0..section_num).collect();
+    order.sort_by_key(|i| program.section_nodes[*i].is_nobits());
+
+    // addresses in the image, sections may follow ones placed later
+    let mut starts: Vec<Option<u64>> = vec![None; section_num];
+    while starts.iter().any(|s| s.is_none()) {
+        let mut is_progress = false;
+
+        for (n, i) in order.iter().enumerate() {
+            if starts[*i].is_some() {
+                continue;
+            }
+
+            let section = &program.section_nodes[*i];
+            let layout = &section.attributes.bin_layout;
+            let align = section.attributes.align.max(1);
+
+            // the first section starts at the origin, others follow the previous one
+            let end_of = |j: usize| starts[j].map(|s| s + program.section_nodes[j].size);
+            let start = match (layout.start, &layout.follows) {
+                (Some(start), _) => Some(start),
+                (None, Some(name)) => {
+                    end_of(find_section(&program, name)).map(|e| align_up(e, align))
+                }
+                (None, None) if n == 0 => Some(origin),
+                (None, None) => end_of(order[n - 1]).map(|e| align_up(e, align)),
+            };
+
+            if start.is_some() {
+                starts[*i] = start;
+                is_progress = true;
+            }
+        }
+
+        if !is_progress {
+            panic!("Circular \"follows\" of sections");
+        }
+    }
+    let starts: Vec<u64> = starts.into_iter().map(|s| s.unwrap()).collect();
+
+    // addresses the code sees, the same as in the image by default
+    let mut vstarts: Vec<Option<u64>> = vec![None; section_num];
+    while vstarts.iter().any(|s| s.is_none()) {
+        let mut is_progress = false;
+
+        for (n, i) in order.iter().enumerate() {
+            if vstarts[*i].is_some() {
+                continue;
+            }
+
+            let section = &program.section_nodes[*i];
+            let layout = &section.attributes.bin_layout;
+            let align = section.attributes.align.max(1);
+
+            let end_of = |j: usize| vstarts[j].map(|s| s + program.section_nodes[j].size);
+            let vstart = match (layout.vstart, &layout.vfollows) {
+                (Some(vstart), _) => Some(vstart),
+                (None, Some(name)) => {
+                    end_of(find_section(&program, name)).map(|e| align_up(e, align))
+                }
+                // nobits sections take no room in the image, so they follow in memory
+                (None, None) if section.is_nobits() && n != 0 => {
+                    end_of(order[n - 1]).map(|e| align_up(e, align))
+                }
+                (None, None) => Some(starts[*i]),
+            };
+
+            if vstart.is_some() {
+                vstarts[*i] = vstart;
+                is_progress = true;
+            }
+        }
+
+        if !is_progress {
+            panic!("Circular \"vfollows\" of sections");
+        }
+    }
+    let vstarts: Vec<u64> = vstarts.into_iter().map(|s| s.unwrap()).collect();
+
+    // "section.<name>.start" and "section.<name>.vstart",
+    // a raw image has no room for relocations of other symbols
+    let external_address = |name: &str| -> Option<i64> {
+        let (section, key) = name.strip_prefix("section.")?.rsplit_once('.')?;
+        let i = program
+            .section_nodes
+            .iter()
+            .position(|s| s.name == section)?;
+        match key {
+            "start" => Some(starts[i] as i64),
+            "vstart" => Some(vstarts[i] as i64),
+            _ => None,
+        }
+    };
+
+    let mut progbits: Vec<usize> = order
+        .into_iter()
+        .filter(|i| !program.section_nodes[*i].is_nobits())
+        .collect();
+    progbits.sort_by_key(|i| starts[*i]);
+
+    let mut bytes = Vec::new();
+    for i in progbits {
+        if starts[i] < origin + bytes.len() as u64 {
+            panic!(
+                "Section \"{}\" overlaps another one or starts before the origin",
+                program.section_nodes[i].name
+            );
+        }
+
+        let data = link_section(&program, i, &vstarts, &external_address);
+        bytes.resize((starts[i] - origin) as usize, 0x0);
+        bytes.extend(data);
+    }
+
+    let mut file = File::create(output_filepath).expect("Failed to create file");
+    file.write_all(&bytes).expect("Failed to write file");
+
+    file
+}
+
+#[test]
+fn test_bin_layout() {
+    use std::{env::temp_dir, fs};
+
+    let asm = "
+        org 0x100
+        section .text
+            dw section..data.start, section..data.vstart, section..tail.start, value
+        section .tail start=0x120
+            db 0xaa
+        section .data follows=.text vstart=0x8000 align=4
+        value:
+            db 1, 2
+    ";
+    let input_filepath = temp_dir().join("rasm_bin_layout.asm");
+    let output_filepath = input_filepath.with_extension("bin");
+    fs::write(&input_filepath, asm).unwrap();
+    gen_bin(&input_filepath, &output_filepath);
+    let bytes = fs::read(&output_filepath).unwrap();
+
+    // .text at the origin, .data after it and .tail at its own start
+    let mut expected = vec![0x08, 0x01, 0x00, 0x80, 0x20, 0x01, 0x00, 0x80, 0x1, 0x2];
+    expected.resize(0x20, 0x0);
+    expected.push(0xaa);
+    assert_eq!(bytes, expected);
+}
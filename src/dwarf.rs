@@ -0,0 +1,765 @@
+use std::{env, path::Path};
+
+use crate::{
+    elf::*,
+    exec::reserve,
+    expr::{BinaryOp, Expr},
+    node::{FrameNode, ProgramNode, SectionNode},
+    operand::Register,
+    parse::{CfiDirective, Instruction, Mnemonic, Operand},
+};
+
+// DWARF 4, 32-bit format
+const DWARF_VERSION: u16 = 4;
+const ARANGES_VERSION: u16 = 2;
+
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_CHILDREN_NO: u8 = 0;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_LANGUAGE: u64 = 0x13;
+const DW_AT_COMP_DIR: u64 = 0x1b;
+const DW_AT_PRODUCER: u64 = 0x25;
+const DW_AT_RANGES: u64 = 0x55;
+
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+// same as gas
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+const CIE_VERSION: u8 = 1;
+// pc_begin of FDEs is a signed 4-byte value relative to itself
+const DW_EH_PE_PCREL_SDATA4: u8 = 0x1b;
+
+const EH_FRAME_HDR_VERSION: u8 = 1;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+// relative to the start of .eh_frame_hdr
+const DW_EH_PE_DATAREL_SDATA4: u8 = 0x3b;
+
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+
+// DWARF numbers of rax, rcx, rdx, rbx, rsp, rbp, rsi and rdi, r8-r15 are the same
+const DWARF_REGISTERS_64: [u64; 8] = [0, 2, 1, 3, 7, 6, 4, 5];
+// return address is rip or eip
+const RETURN_ADDRESS_64: u64 = 16;
+const RETURN_ADDRESS_32: u64 = 8;
+const STACK_POINTER_64: u64 = 7;
+const STACK_POINTER_32: u64 = 4;
+
+// contents of a debug section, addresses are left to relocations
+struct DebugData {
+    bytes: Vec<u8>,
+    // (offset, size, value)
+    fields: Vec<(usize, u8, Expr)>,
+}
+
+impl DebugData {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn uleb128(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn sleb128(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.bytes.extend(s.as_bytes());
+        self.bytes.push(0x0);
+    }
+
+    // offset in a section or address, relocated in objects
+    fn position(&mut self, size: u8, section_index: usize, offset: u64) {
+        self.fields.push((
+            self.bytes.len(),
+            size,
+            Expr::Position(section_index, offset),
+        ));
+        self.bytes.extend(vec![0x0; size as usize]);
+    }
+
+    // value relative to the field, "target - $" in the section
+    fn relative(&mut self, own_index: usize, section_index: usize, offset: u64) {
+        self.fields.push((
+            self.bytes.len(),
+            4,
+            Expr::Binary(
+                BinaryOp::Sub,
+                Box::new(Expr::Position(section_index, offset)),
+                Box::new(Expr::Position(own_index, self.bytes.len() as u64)),
+            ),
+        ));
+        self.bytes.extend([0x0; 4]);
+    }
+
+    // pad the entry starting at "start" to the alignment
+    fn align_entry(&mut self, start: usize, align: usize) {
+        while !(self.bytes.len() - start).is_multiple_of(align) {
+            self.u8(DW_CFA_NOP);
+        }
+    }
+
+    // unit_length of a unit starting at "start"
+    fn patch_length(&mut self, start: usize) {
+        let length = (self.bytes.len() - start - 4) as u32;
+        self.bytes[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    fn into_section(self, name: &str, flags: u64) -> SectionNode {
+        let mut section = SectionNode::new(name.to_string());
+        section.attributes.s_type = SHT_PROGBITS;
+        section.attributes.flags = flags;
+        section.attributes.align = 1;
+        if flags & SHF_STRINGS != 0 {
+            section.attributes.entry_size = 1;
+        }
+
+        let data = |mnemonic: Mnemonic, operands: Vec<Operand>| Instruction {
+            mnemonic,
+            operands,
+            times: 1,
+            repeat: None,
+            bits: 64,
+            line: 0,
+            file: 0,
+        };
+        let bytes = |bytes: &[u8]| {
+            data(
+                Mnemonic::Db,
+                bytes
+                    .iter()
+                    .map(|b| Operand::Immediate(Expr::Number(*b as i64)))
+                    .collect(),
+            )
+        };
+
+        let mut start = 0;
+        for (offset, size, expr) in self.fields {
+            if start < offset {
+                section.push_instruction(bytes(&self.bytes[start..offset]));
+            }
+            let mnemonic = if size == 8 {
+                Mnemonic::Dq
+            } else {
+                Mnemonic::Dd
+            };
+            section.push_instruction(data(mnemonic, vec![Operand::Immediate(expr)]));
+            start = offset + size as usize;
+        }
+        if start < self.bytes.len() {
+            section.push_instruction(bytes(&self.bytes[start..]));
+        }
+
+        section
+    }
+}
+
+// rows of the line table, (offset, file, line) of each instruction
+fn line_rows(section: &SectionNode) -> Vec<(u64, usize, usize)> {
+    let mut rows = Vec::new();
+    let mut offset = 0;
+
+    for ins in section.instructions.iter() {
+        // data has no line of its own
+        if ins.line != 0 && ins.mnemonic.data_size().is_none() {
+            rows.push((offset, ins.file, ins.line));
+        }
+        offset += ins.len();
+    }
+
+    rows
+}
+
+// .debug_* sections mapping the code back to the source lines
+pub fn add_debug_sections(program: &mut ProgramNode, input_filepath: &Path, address_size: u8) {
+    let code_sections: Vec<usize> = (0..program.section_nodes.len())
+        .filter(|i| {
+            let section = &program.section_nodes[*i];
+            section.attributes.flags & SHF_EXECINSTR != 0 && !line_rows(section).is_empty()
+        })
+        .collect();
+
+    let first_index = program.section_nodes.len();
+    let abbrev_index = first_index;
+    let info_index = first_index + 1;
+    let line_index = first_index + 2;
+    let str_index = first_index + 3;
+    let ranges_index = first_index + 5;
+
+    let mut strings = DebugData::new();
+    let name_offset = strings.bytes.len() as u64;
+    strings.string(input_filepath.to_str().unwrap());
+    let comp_dir_offset = strings.bytes.len() as u64;
+    strings.string(&env::current_dir().unwrap_or_default().to_string_lossy());
+    let producer_offset = strings.bytes.len() as u64;
+    strings.string(&format!("rasm {}", env!("CARGO_PKG_VERSION")));
+
+    // one code section has a pc range, more need a range list
+    let has_ranges = code_sections.len() > 1;
+
+    let mut abbrev = DebugData::new();
+    abbrev.uleb128(1);
+    abbrev.uleb128(DW_TAG_COMPILE_UNIT);
+    abbrev.u8(DW_CHILDREN_NO);
+    let mut attributes = vec![
+        (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+    ];
+    if has_ranges {
+        attributes.push((DW_AT_RANGES, DW_FORM_SEC_OFFSET));
+    } else {
+        attributes.push((DW_AT_HIGH_PC, DW_FORM_DATA4));
+    }
+    attributes.extend([
+        (DW_AT_NAME, DW_FORM_STRP),
+        (DW_AT_COMP_DIR, DW_FORM_STRP),
+        (DW_AT_PRODUCER, DW_FORM_STRP),
+        (DW_AT_LANGUAGE, DW_FORM_DATA2),
+    ]);
+    for (attribute, form) in attributes {
+        abbrev.uleb128(attribute);
+        abbrev.uleb128(form);
+    }
+    abbrev.uleb128(0);
+    abbrev.uleb128(0);
+    abbrev.uleb128(0);
+
+    let mut info = DebugData::new();
+    info.u32(0);
+    info.u16(DWARF_VERSION);
+    info.position(4, abbrev_index, 0);
+    info.u8(address_size);
+    info.uleb128(1);
+    info.position(4, line_index, 0);
+    match code_sections.first() {
+        Some(section_index) if !has_ranges => {
+            info.position(address_size, *section_index, 0);
+            info.u32(program.section_nodes[*section_index].size as u32);
+        }
+        _ => {
+            // range list entries are relative to this
+            info.bytes.extend(vec![0x0; address_size as usize]);
+            if has_ranges {
+                info.position(4, ranges_index, 0);
+            } else {
+                info.u32(0);
+            }
+        }
+    }
+    info.position(4, str_index, name_offset);
+    info.position(4, str_index, comp_dir_offset);
+    info.position(4, str_index, producer_offset);
+    info.u16(DW_LANG_MIPS_ASSEMBLER);
+    info.patch_length(0);
+
+    let mut line = DebugData::new();
+    line.u32(0);
+    line.u16(DWARF_VERSION);
+    line.u32(0);
+    let header_start = line.bytes.len();
+    // minimum_instruction_length, maximum_operations_per_instruction and default_is_stmt
+    line.u8(1);
+    line.u8(1);
+    line.u8(1);
+    line.u8(LINE_BASE as u8);
+    line.u8(LINE_RANGE);
+    line.u8(OPCODE_BASE);
+    line.bytes.extend(STANDARD_OPCODE_LENGTHS);
+    // no include directories, the files are relative to the compilation directory
+    line.u8(0);
+    let input_name = input_filepath.to_str().unwrap().to_string();
+    for name in [&input_name].into_iter().chain(program.files.iter()) {
+        line.string(name);
+        line.uleb128(0);
+        line.uleb128(0);
+        line.uleb128(0);
+    }
+    line.u8(0);
+    let header_length = (line.bytes.len() - header_start) as u32;
+    line.bytes[header_start - 4..header_start].copy_from_slice(&header_length.to_le_bytes());
+
+    for section_index in code_sections.iter() {
+        let section = &program.section_nodes[*section_index];
+
+        line.u8(0);
+        line.uleb128(1 + address_size as u64);
+        line.u8(DW_LNE_SET_ADDRESS);
+        line.position(address_size, *section_index, 0);
+
+        // files are numbered from 1
+        let (mut address, mut current_file, mut current_line) = (0, 0, 1);
+        for (offset, row_file, row_line) in line_rows(section) {
+            if row_file != current_file {
+                line.u8(DW_LNS_SET_FILE);
+                line.uleb128(row_file as u64 + 1);
+                current_file = row_file;
+            }
+            if offset != address {
+                line.u8(DW_LNS_ADVANCE_PC);
+                line.uleb128(offset - address);
+                address = offset;
+            }
+            if row_line != current_line {
+                line.u8(DW_LNS_ADVANCE_LINE);
+                line.sleb128(row_line as i64 - current_line as i64);
+                current_line = row_line;
+            }
+            line.u8(DW_LNS_COPY);
+        }
+
+        if section.size != address {
+            line.u8(DW_LNS_ADVANCE_PC);
+            line.uleb128(section.size - address);
+        }
+        line.u8(0);
+        line.uleb128(1);
+        line.u8(DW_LNE_END_SEQUENCE);
+    }
+    line.patch_length(0);
+
+    let mut aranges = DebugData::new();
+    aranges.u32(0);
+    aranges.u16(ARANGES_VERSION);
+    aranges.position(4, info_index, 0);
+    aranges.u8(address_size);
+    // segment_selector_size
+    aranges.u8(0);
+    // tuples are aligned to twice the address size
+    while !aranges
+        .bytes
+        .len()
+        .is_multiple_of(address_size as usize * 2)
+    {
+        aranges.u8(0);
+    }
+    let mut ranges = DebugData::new();
+    for section_index in code_sections.iter() {
+        let size = program.section_nodes[*section_index].size;
+        aranges.position(address_size, *section_index, 0);
+        ranges.position(address_size, *section_index, 0);
+        ranges.position(address_size, *section_index, size);
+
+        if address_size == 8 {
+            aranges.bytes.extend(size.to_le_bytes());
+        } else {
+            aranges.u32(size as u32);
+        }
+    }
+    aranges.bytes.extend(vec![0x0; address_size as usize * 2]);
+    aranges.patch_length(0);
+    ranges.bytes.extend(vec![0x0; address_size as usize * 2]);
+
+    program
+        .section_nodes
+        .push(abbrev.into_section(".debug_abbrev", 0));
+    program
+        .section_nodes
+        .push(info.into_section(".debug_info", 0));
+    program
+        .section_nodes
+        .push(line.into_section(".debug_line", 0));
+    program
+        .section_nodes
+        .push(strings.into_section(".debug_str", SHF_MERGE | SHF_STRINGS));
+    program
+        .section_nodes
+        .push(aranges.into_section(".debug_aranges", 0));
+    if has_ranges {
+        program
+            .section_nodes
+            .push(ranges.into_section(".debug_ranges", 0));
+    }
+}
+
+fn dwarf_register(register: &Register, bits: u8) -> u64 {
+    if bits == 64 && register.size == 8 {
+        return match DWARF_REGISTERS_64.get(register.number as usize) {
+            Some(number) => *number,
+            None => register.number as u64,
+        };
+    }
+
+    // i386 numbers are the same as the encoding
+    if bits != 64 && register.size == 4 && !register.is_extended() {
+        return register.number as u64;
+    }
+
+    panic!("{:?} can't be used in CFI in {}-bit mode", register, bits);
+}
+
+fn advance_location(data: &mut DebugData, delta: u64) {
+    match delta {
+        0 => (),
+        1..=0x3f => data.u8(DW_CFA_ADVANCE_LOC | delta as u8),
+        0x40..=0xff => {
+            data.u8(DW_CFA_ADVANCE_LOC1);
+            data.u8(delta as u8);
+        }
+        0x100..=0xffff => {
+            data.u8(DW_CFA_ADVANCE_LOC2);
+            data.u16(delta as u16);
+        }
+        _ => {
+            data.u8(DW_CFA_ADVANCE_LOC4);
+            data.u32(delta as u32);
+        }
+    }
+}
+
+// register saved at the offset from the CFA
+fn saved_register(data: &mut DebugData, register: u64, offset: i64, word: i64) {
+    if offset % word != 0 {
+        panic!("CFI offset {} is not a multiple of {}", offset, word);
+    }
+
+    // factored by the data alignment, -word
+    let factored = offset / -word;
+    if register < 0x40 && factored >= 0 {
+        data.u8(DW_CFA_OFFSET | register as u8);
+        data.uleb128(factored as u64);
+    } else {
+        data.u8(DW_CFA_OFFSET_EXTENDED_SF);
+        data.uleb128(register);
+        data.sleb128(factored);
+    }
+}
+
+fn restored_register(data: &mut DebugData, register: u64) {
+    if register < 0x40 {
+        data.u8(DW_CFA_RESTORE | register as u8);
+    } else {
+        data.u8(DW_CFA_RESTORE_EXTENDED);
+        data.uleb128(register);
+    }
+}
+
+fn cfa_offset(data: &mut DebugData, offset: i64) {
+    if offset < 0 {
+        panic!("CFA offset {} is negative", offset);
+    }
+
+    data.u8(DW_CFA_DEF_CFA_OFFSET);
+    data.uleb128(offset as u64);
+}
+
+// call frame instructions of a procedure
+fn frame_instructions(data: &mut DebugData, frame: &FrameNode, bits: u8) {
+    let word = bits as i64 / 8;
+    // right after the call only the return address is on the stack
+    let mut offset = word;
+    let mut states = Vec::new();
+    let mut location = frame.start;
+
+    for (at, directive) in frame.directives.iter() {
+        advance_location(data, at - location);
+        location = *at;
+
+        match directive {
+            CfiDirective::DefCfa(register, new_offset) => {
+                data.u8(DW_CFA_DEF_CFA);
+                data.uleb128(dwarf_register(register, bits));
+                data.uleb128(*new_offset as u64);
+                offset = *new_offset;
+            }
+            CfiDirective::DefCfaRegister(register) => {
+                data.u8(DW_CFA_DEF_CFA_REGISTER);
+                data.uleb128(dwarf_register(register, bits));
+            }
+            CfiDirective::DefCfaOffset(new_offset) => {
+                offset = *new_offset;
+                cfa_offset(data, offset);
+            }
+            CfiDirective::AdjustCfaOffset(delta) => {
+                offset += delta;
+                cfa_offset(data, offset);
+            }
+            CfiDirective::Offset(register, at) => {
+                saved_register(data, dwarf_register(register, bits), *at, word);
+            }
+            CfiDirective::Restore(register) => {
+                restored_register(data, dwarf_register(register, bits));
+            }
+            CfiDirective::Push(register) => {
+                offset += word;
+                cfa_offset(data, offset);
+                saved_register(data, dwarf_register(register, bits), -offset, word);
+            }
+            CfiDirective::Pop(register) => {
+                offset -= word;
+                cfa_offset(data, offset);
+                restored_register(data, dwarf_register(register, bits));
+            }
+            CfiDirective::RememberState => {
+                data.u8(DW_CFA_REMEMBER_STATE);
+                states.push(offset);
+            }
+            CfiDirective::RestoreState => {
+                data.u8(DW_CFA_RESTORE_STATE);
+                offset = match states.pop() {
+                    Some(offset) => offset,
+                    None => panic!("cfi_restore_state without cfi_remember_state"),
+                };
+            }
+            CfiDirective::StartProc | CfiDirective::EndProc => unreachable!(),
+        }
+    }
+}
+
+// .eh_frame with a CIE and an FDE for each procedure
+pub fn add_eh_frame(program: &mut ProgramNode, bits: u8) {
+    if program.frames.is_empty() {
+        return;
+    }
+
+    let eh_frame_index = program.section_nodes.len();
+    let word = bits as usize / 8;
+    let (return_address, stack_pointer) = match bits {
+        64 => (RETURN_ADDRESS_64, STACK_POINTER_64),
+        _ => (RETURN_ADDRESS_32, STACK_POINTER_32),
+    };
+
+    let mut data = DebugData::new();
+    data.u32(0);
+    // CIE id
+    data.u32(0);
+    data.u8(CIE_VERSION);
+    data.string("zR");
+    // code alignment, data alignment and the return address register
+    data.uleb128(1);
+    data.sleb128(-(word as i64));
+    data.uleb128(return_address);
+    // augmentation data
+    data.uleb128(1);
+    data.u8(DW_EH_PE_PCREL_SDATA4);
+    // CFA is the stack pointer before the call, the return address is below it
+    data.u8(DW_CFA_DEF_CFA);
+    data.uleb128(stack_pointer);
+    data.uleb128(word as u64);
+    data.u8(DW_CFA_OFFSET | return_address as u8);
+    data.uleb128(1);
+    data.align_entry(0, word);
+    data.patch_length(0);
+
+    for frame in program.frames.iter() {
+        let start = data.bytes.len();
+        data.u32(0);
+        // distance back to the CIE
+        data.u32(data.bytes.len() as u32);
+        data.relative(eh_frame_index, frame.section_index, frame.start);
+        data.u32((frame.end - frame.start) as u32);
+        data.uleb128(0);
+        frame_instructions(&mut data, frame, bits);
+        data.align_entry(start, word);
+        data.patch_length(start);
+    }
+
+    let mut section = data.into_section(".eh_frame", SHF_ALLOC);
+    section.attributes.align = word as u64;
+    if bits == 64 {
+        section.attributes.s_type = SHT_X86_64_UNWIND;
+    }
+    program.section_nodes.push(section);
+}
+
+// .eh_frame_hdr of an image, filled in by eh_frame_hdr once .eh_frame is linked,
+// unwinders find the FDEs through it and PT_GNU_EH_FRAME
+pub fn add_eh_frame_hdr(program: &mut ProgramNode) {
+    if program.frames.is_empty() {
+        return;
+    }
+
+    // version, encodings, eh_frame_ptr, fde_count and a pair for each FDE
+    let mut section = SectionNode::new(".eh_frame_hdr".to_string());
+    section.attributes.s_type = SHT_PROGBITS;
+    section.attributes.flags = SHF_ALLOC;
+    section.attributes.align = 4;
+    section.push_instruction(reserve(12 + 8 * program.frames.len() as u64));
+    program.section_nodes.push(section);
+}
+
+// contents of .eh_frame_hdr at "address" for the linked .eh_frame,
+// a binary search table of the FDEs sorted by their start
+pub fn eh_frame_hdr(eh_frame: &[u8], eh_frame_address: u64, address: u64) -> Vec<u8> {
+    let word = |offset: usize| u32::from_le_bytes(eh_frame[offset..offset + 4].try_into().unwrap());
+
+    // (pc_begin, FDE address), CIEs have id 0
+    let mut table = Vec::new();
+    let mut offset = 0;
+    while offset < eh_frame.len() {
+        if word(offset + 4) != 0 {
+            let pc_begin = eh_frame_address as i64 + offset as i64 + 8;
+            table.push((
+                pc_begin + word(offset + 8) as i32 as i64,
+                eh_frame_address as i64 + offset as i64,
+            ));
+        }
+        offset += 4 + word(offset) as usize;
+    }
+    table.sort();
+
+    let mut data = DebugData::new();
+    data.u8(EH_FRAME_HDR_VERSION);
+    // eh_frame_ptr, fde_count and the table
+    data.u8(DW_EH_PE_PCREL_SDATA4);
+    data.u8(DW_EH_PE_UDATA4);
+    data.u8(DW_EH_PE_DATAREL_SDATA4);
+    data.u32((eh_frame_address as i64 - (address + 4) as i64) as u32);
+    data.u32(table.len() as u32);
+    for (pc_begin, fde) in table {
+        data.u32((pc_begin - address as i64) as u32);
+        data.u32((fde - address as i64) as u32);
+    }
+
+    data.bytes
+}
+
+#[test]
+fn test_leb128() {
+    let mut data = DebugData::new();
+    data.uleb128(2);
+    data.uleb128(624485);
+    data.sleb128(-1);
+    data.sleb128(63);
+    data.sleb128(64);
+    data.sleb128(-123456);
+    assert_eq!(
+        data.bytes,
+        [0x02, 0xe5, 0x8e, 0x26, 0x7f, 0x3f, 0xc0, 0x00, 0xc0, 0xbb, 0x78]
+    );
+}
+
+#[test]
+fn test_debug_line() {
+    use crate::{generator::gen_program, parse::parse};
+
+    let asm = "section .text\nnop\nmov eax, 1\n\nret";
+    let tokens: Vec<_> = asm.lines().map(parse).collect();
+    let mut program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+    add_debug_sections(&mut program, Path::new("test.asm"), 8);
+    let index = program
+        .section_nodes
+        .iter()
+        .position(|s| s.name == ".debug_line")
+        .unwrap();
+    let bytes = program.encode_section(index).0;
+
+    let mut at = 0;
+    let mut next = || {
+        at += 1;
+        bytes[at - 1]
+    };
+    let uleb128 = |next: &mut dyn FnMut() -> u8| {
+        let (mut value, mut shift) = (0u64, 0);
+        loop {
+            let byte = next();
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return (value, shift);
+            }
+        }
+    };
+
+    // the program starts after unit_length, version and header_length
+    let header_length = u32::from_le_bytes(bytes[6..10].try_into().unwrap()) as usize;
+    for _ in 0..10 + header_length {
+        next();
+    }
+
+    // (address, file, line) of each row
+    let (mut address, mut file, mut line) = (0, 1, 1);
+    let mut rows = Vec::new();
+    loop {
+        match next() {
+            0 => {
+                let length = uleb128(&mut next).0;
+                let opcode = next();
+                for _ in 1..length {
+                    next();
+                }
+                if opcode == DW_LNE_END_SEQUENCE {
+                    break;
+                }
+            }
+            DW_LNS_COPY => rows.push((address, file, line)),
+            DW_LNS_ADVANCE_PC => address += uleb128(&mut next).0,
+            DW_LNS_ADVANCE_LINE => {
+                let (value, shift) = uleb128(&mut next);
+                // sign extended from the last bit read
+                line += (value << (64 - shift)) as i64 >> (64 - shift);
+            }
+            DW_LNS_SET_FILE => file = uleb128(&mut next).0,
+            opcode => panic!("Unexpected opcode {}", opcode),
+        }
+    }
+    assert_eq!(rows, [(0, 1, 2), (1, 1, 3), (6, 1, 5)]);
+    // the sequence ends after the last instruction
+    assert_eq!(address, 7);
+    assert_eq!(at, bytes.len());
+}
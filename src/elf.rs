@@ -3,6 +3,172 @@ use std::{mem::size_of, slice::from_raw_parts};
 
 pub const MAGIC_NUMS: [u8; 4] = [0x7f, 0x45, 0x4c, 0x46];
 
+// section types
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_HASH: u32 = 5;
+pub const SHT_DYNAMIC: u32 = 6;
+pub const SHT_NOTE: u32 = 7;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_DYNSYM: u32 = 11;
+pub const SHT_INIT_ARRAY: u32 = 14;
+pub const SHT_FINI_ARRAY: u32 = 15;
+pub const SHT_PREINIT_ARRAY: u32 = 16;
+pub const SHT_GROUP: u32 = 17;
+pub const SHT_SYMTAB_SHNDX: u32 = 18;
+pub const SHT_X86_64_UNWIND: u32 = 0x70000001;
+
+// section flags
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_MERGE: u64 = 0x10;
+pub const SHF_STRINGS: u64 = 0x20;
+pub const SHF_INFO_LINK: u64 = 0x40;
+pub const SHF_GROUP: u64 = 0x200;
+pub const SHF_TLS: u64 = 0x400;
+
+// section group flags
+pub const GRP_COMDAT: u32 = 0x1;
+
+// special section indexes
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_LORESERVE: u16 = 0xff00;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_COMMON: u16 = 0xfff2;
+pub const SHN_XINDEX: u16 = 0xffff;
+
+// symbol bindings
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+// symbol types
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+pub const STT_TLS: u8 = 6;
+
+// symbol visibilities
+pub const STV_DEFAULT: u8 = 0;
+pub const STV_INTERNAL: u8 = 1;
+pub const STV_HIDDEN: u8 = 2;
+pub const STV_PROTECTED: u8 = 3;
+
+// object file types
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+// machine types
+pub const EM_386: u16 = 3;
+pub const EM_X86_64: u16 = 62;
+
+// segment types
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_NOTE: u32 = 4;
+pub const PT_GNU_EH_FRAME: u32 = 0x6474e550;
+pub const PT_GNU_STACK: u32 = 0x6474e551;
+pub const PT_GNU_PROPERTY: u32 = 0x6474e553;
+
+// GNU notes
+pub const NT_GNU_BUILD_ID: u32 = 3;
+pub const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+pub const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+pub const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 0x1;
+pub const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 0x2;
+
+// segment flags
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+// relocation types
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_GLOB_DAT: u32 = 6;
+pub const R_X86_64_JUMP_SLOT: u32 = 7;
+pub const R_X86_64_RELATIVE: u32 = 8;
+pub const R_X86_64_GOTPCREL: u32 = 9;
+pub const R_X86_64_32: u32 = 10;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_16: u32 = 12;
+pub const R_X86_64_PC16: u32 = 13;
+pub const R_X86_64_8: u32 = 14;
+pub const R_X86_64_PC8: u32 = 15;
+pub const R_X86_64_TLSGD: u32 = 19;
+pub const R_X86_64_TLSLD: u32 = 20;
+pub const R_X86_64_DTPOFF32: u32 = 21;
+pub const R_X86_64_GOTTPOFF: u32 = 22;
+pub const R_X86_64_TPOFF32: u32 = 23;
+
+// dynamic section tags
+pub const DT_NULL: i64 = 0;
+pub const DT_NEEDED: i64 = 1;
+pub const DT_PLTRELSZ: i64 = 2;
+pub const DT_PLTGOT: i64 = 3;
+pub const DT_HASH: i64 = 4;
+pub const DT_STRTAB: i64 = 5;
+pub const DT_SYMTAB: i64 = 6;
+pub const DT_RELA: i64 = 7;
+pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+pub const DT_STRSZ: i64 = 10;
+pub const DT_SYMENT: i64 = 11;
+pub const DT_SONAME: i64 = 14;
+pub const DT_PLTREL: i64 = 20;
+pub const DT_TEXTREL: i64 = 22;
+pub const DT_JMPREL: i64 = 23;
+pub const DT_INIT_ARRAY: i64 = 25;
+pub const DT_FINI_ARRAY: i64 = 26;
+pub const DT_INIT_ARRAYSZ: i64 = 27;
+pub const DT_FINI_ARRAYSZ: i64 = 28;
+pub const DT_FLAGS: i64 = 30;
+
+// DT_FLAGS values
+pub const DF_BIND_NOW: u64 = 0x8;
+
+// i386 relocation types
+pub const R_386_32: u32 = 1;
+pub const R_386_PC32: u32 = 2;
+pub const R_386_PLT32: u32 = 4;
+pub const R_386_TLS_IE: u32 = 15;
+pub const R_386_TLS_LE: u32 = 17;
+pub const R_386_TLS_GD: u32 = 18;
+pub const R_386_TLS_LDM: u32 = 19;
+pub const R_386_16: u32 = 20;
+pub const R_386_PC16: u32 = 21;
+pub const R_386_8: u32 = 22;
+pub const R_386_PC8: u32 = 23;
+pub const R_386_TLS_LDO_32: u32 = 32;
+
+pub const fn st_info(bind: u8, s_type: u8) -> u8 {
+    (bind << 4) | (s_type & 0xf)
+}
+
+// st_shndx of a section, larger indexes go in .symtab_shndx
+pub const fn symbol_section_index(index: usize) -> u16 {
+    if index >= SHN_LORESERVE as usize {
+        return SHN_XINDEX;
+    }
+    index as u16
+}
+
+pub const fn r_info(symbol: u32, r_type: u32) -> u64 {
+    ((symbol as u64) << 32) | r_type as u64
+}
+
+pub const fn elf32_r_info(symbol: u32, r_type: u32) -> u32 {
+    (symbol << 8) | (r_type & 0xff)
+}
+
 #[derive(Debug)]
 #[repr(C, align(16))]
 pub struct Elf64Header {
@@ -30,7 +196,7 @@ pub struct Elf64Header {
 
 impl Elf64Header {
     pub fn template() -> Self {
-        return Self {
+        Self {
             magic_nums: MAGIC_NUMS,
             class: 0x2,
             endian: 0x1,
@@ -38,8 +204,8 @@ impl Elf64Header {
             abi: 0x0,
             abi_version: 0x0,
             reserved: [0x0; 7],
-            object_type: [0x1, 0x0],
-            machine_type: [0x3e, 0x0],
+            object_type: ET_REL.to_le_bytes(),
+            machine_type: EM_X86_64.to_le_bytes(),
             version2: [0x1, 0x0, 0x0, 0x0],
             entry: [0x0; 8],
             program_header_offset: [0x0; 8],
@@ -51,25 +217,47 @@ impl Elf64Header {
             section_header_size: [0x40, 0x0],
             section_header_num: [0x5, 0x0],
             section_header_str_index: [0x2, 0x0],
-        };
+        }
     }
 
     pub fn as_u8_slice(&self) -> &[u8] {
-        return unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) };
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_object_type(&mut self, object_type: u16) {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, object_type);
+        self.object_type = buf;
+    }
+
+    pub fn set_entry(&mut self, entry: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, entry);
+        self.entry = buf;
+    }
+
+    pub fn set_program_header_offset(&mut self, program_header_offset: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, program_header_offset);
+        self.program_header_offset = buf;
     }
 
-    pub fn section_header_size(&self) -> u16 {
-        return LittleEndian::read_u16(&self.section_header_size);
+    pub fn set_section_header_offset(&mut self, section_header_offset: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, section_header_offset);
+        self.section_header_offset = buf;
     }
 
-    pub fn set_section_header_size(&mut self, section_header_size: u16) {
+    pub fn set_program_header_size(&mut self, program_header_size: u16) {
         let mut buf = [0; 2];
-        LittleEndian::write_u16(&mut buf, section_header_size);
-        self.section_header_size = buf;
+        LittleEndian::write_u16(&mut buf, program_header_size);
+        self.program_header_size = buf;
     }
 
-    pub fn section_header_num(&self) -> u16 {
-        return LittleEndian::read_u16(&self.section_header_num);
+    pub fn set_program_header_num(&mut self, program_header_num: u16) {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, program_header_num);
+        self.program_header_num = buf;
     }
 
     pub fn set_section_header_num(&mut self, section_header_num: u16) {
@@ -78,10 +266,6 @@ impl Elf64Header {
         self.section_header_num = buf;
     }
 
-    pub fn section_header_str_index(&self) -> u16 {
-        return LittleEndian::read_u16(&self.section_header_str_index);
-    }
-
     pub fn set_section_header_str_index(&mut self, section_header_str_index: u16) {
         let mut buf = [0; 2];
         LittleEndian::write_u16(&mut buf, section_header_str_index);
@@ -89,7 +273,7 @@ impl Elf64Header {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 #[repr(C, align(16))]
 pub struct Elf64SectionHeader {
     name: [u8; 4],
@@ -105,6 +289,7 @@ pub struct Elf64SectionHeader {
 }
 
 impl Elf64SectionHeader {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: u32,
         s_type: u32,
@@ -129,15 +314,15 @@ impl Elf64SectionHeader {
         header.set_align(align);
         header.set_entry_size(entry_size);
 
-        return header;
+        header
     }
 
     pub fn as_u8_slice(&self) -> &[u8] {
-        return unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) };
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
     }
 
     pub fn name(&self) -> u32 {
-        return LittleEndian::read_u32(&self.name);
+        LittleEndian::read_u32(&self.name)
     }
 
     pub fn set_name(&mut self, name: u32) {
@@ -147,7 +332,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn s_type(&self) -> u32 {
-        return LittleEndian::read_u32(&self.s_type);
+        LittleEndian::read_u32(&self.s_type)
     }
 
     pub fn set_s_type(&mut self, s_type: u32) {
@@ -157,7 +342,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn flags(&self) -> u64 {
-        return LittleEndian::read_u64(&self.flags);
+        LittleEndian::read_u64(&self.flags)
     }
 
     pub fn set_flags(&mut self, flags: u64) {
@@ -167,7 +352,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn addr(&self) -> u64 {
-        return LittleEndian::read_u64(&self.addr);
+        LittleEndian::read_u64(&self.addr)
     }
 
     pub fn set_addr(&mut self, addr: u64) {
@@ -177,7 +362,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn offset(&self) -> u64 {
-        return LittleEndian::read_u64(&self.offset);
+        LittleEndian::read_u64(&self.offset)
     }
 
     pub fn set_offset(&mut self, offset: u64) {
@@ -187,7 +372,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn size(&self) -> u64 {
-        return LittleEndian::read_u64(&self.size);
+        LittleEndian::read_u64(&self.size)
     }
 
     pub fn set_size(&mut self, size: u64) {
@@ -197,7 +382,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn link(&self) -> u32 {
-        return LittleEndian::read_u32(&self.link);
+        LittleEndian::read_u32(&self.link)
     }
 
     pub fn set_link(&mut self, link: u32) {
@@ -207,7 +392,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn info(&self) -> u32 {
-        return LittleEndian::read_u32(&self.info);
+        LittleEndian::read_u32(&self.info)
     }
 
     pub fn set_info(&mut self, info: u32) {
@@ -217,7 +402,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn align(&self) -> u64 {
-        return LittleEndian::read_u64(&self.align);
+        LittleEndian::read_u64(&self.align)
     }
 
     pub fn set_align(&mut self, align: u64) {
@@ -227,7 +412,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn entry_size(&self) -> u64 {
-        return LittleEndian::read_u64(&self.entry_size);
+        LittleEndian::read_u64(&self.entry_size)
     }
 
     pub fn set_entry_size(&mut self, entry_size: u64) {
@@ -237,25 +422,9 @@ impl Elf64SectionHeader {
     }
 }
 
-impl Default for Elf64SectionHeader {
-    fn default() -> Self {
-        Self {
-            name: [0; 4],
-            s_type: [0; 4],
-            flags: [0; 8],
-            addr: [0; 8],
-            offset: [0; 8],
-            size: [0; 8],
-            link: [0; 4],
-            info: [0; 4],
-            align: [0; 8],
-            entry_size: [0; 8],
-        }
-    }
-}
-
-#[derive(Debug)]
-#[repr(C, align(16))]
+// 24 bytes per entry, so no extra alignment padding
+#[derive(Debug, Default)]
+#[repr(C)]
 pub struct Elf64SymbolTableSection {
     name: [u8; 4],
     info: u8,
@@ -275,15 +444,15 @@ impl Elf64SymbolTableSection {
         section.set_value(value);
         section.set_size(size);
 
-        return section;
+        section
     }
 
     pub fn as_u8_slice(&self) -> &[u8] {
-        return unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) };
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
     }
 
     pub fn name(&self) -> u32 {
-        return LittleEndian::read_u32(&self.name);
+        LittleEndian::read_u32(&self.name)
     }
 
     pub fn set_name(&mut self, name: u32) {
@@ -293,7 +462,7 @@ impl Elf64SymbolTableSection {
     }
 
     pub fn info(&self) -> u8 {
-        return self.info;
+        self.info
     }
 
     pub fn set_info(&mut self, info: u8) {
@@ -301,7 +470,7 @@ impl Elf64SymbolTableSection {
     }
 
     pub fn other(&self) -> u8 {
-        return self.other;
+        self.other
     }
 
     pub fn set_other(&mut self, other: u8) {
@@ -309,7 +478,7 @@ impl Elf64SymbolTableSection {
     }
 
     pub fn index(&self) -> u16 {
-        return LittleEndian::read_u16(&self.index);
+        LittleEndian::read_u16(&self.index)
     }
 
     pub fn set_index(&mut self, index: u16) {
@@ -319,7 +488,7 @@ impl Elf64SymbolTableSection {
     }
 
     pub fn value(&self) -> u64 {
-        return LittleEndian::read_u64(&self.value);
+        LittleEndian::read_u64(&self.value)
     }
 
     pub fn set_value(&mut self, value: u64) {
@@ -329,7 +498,7 @@ impl Elf64SymbolTableSection {
     }
 
     pub fn size(&self) -> u64 {
-        return LittleEndian::read_u64(&self.size);
+        LittleEndian::read_u64(&self.size)
     }
 
     pub fn set_size(&mut self, size: u64) {
@@ -339,15 +508,428 @@ impl Elf64SymbolTableSection {
     }
 }
 
-impl Default for Elf64SymbolTableSection {
-    fn default() -> Self {
-        return Self {
-            name: [0; 4],
-            info: 0,
-            other: 0,
-            index: [0; 2],
-            value: [0; 8],
-            size: [0; 8],
-        };
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf64ProgramHeader {
+    p_type: [u8; 4],
+    flags: [u8; 4],
+    offset: [u8; 8],
+    vaddr: [u8; 8],
+    paddr: [u8; 8],
+    file_size: [u8; 8],
+    memory_size: [u8; 8],
+    align: [u8; 8],
+}
+
+impl Elf64ProgramHeader {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        p_type: u32,
+        flags: u32,
+        offset: u64,
+        vaddr: u64,
+        paddr: u64,
+        file_size: u64,
+        memory_size: u64,
+        align: u64,
+    ) -> Self {
+        let mut header = Self::default();
+        header.set_p_type(p_type);
+        header.set_flags(flags);
+        header.set_offset(offset);
+        header.set_vaddr(vaddr);
+        header.set_paddr(paddr);
+        header.set_file_size(file_size);
+        header.set_memory_size(memory_size);
+        header.set_align(align);
+
+        header
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_p_type(&mut self, p_type: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, p_type);
+        self.p_type = buf;
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, flags);
+        self.flags = buf;
+    }
+
+    pub fn set_offset(&mut self, offset: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, offset);
+        self.offset = buf;
+    }
+
+    pub fn set_vaddr(&mut self, vaddr: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, vaddr);
+        self.vaddr = buf;
+    }
+
+    pub fn set_paddr(&mut self, paddr: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, paddr);
+        self.paddr = buf;
+    }
+
+    pub fn set_file_size(&mut self, file_size: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, file_size);
+        self.file_size = buf;
+    }
+
+    pub fn set_memory_size(&mut self, memory_size: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, memory_size);
+        self.memory_size = buf;
+    }
+
+    pub fn set_align(&mut self, align: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, align);
+        self.align = buf;
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf64Rela {
+    offset: [u8; 8],
+    info: [u8; 8],
+    addend: [u8; 8],
+}
+
+impl Elf64Rela {
+    pub fn new(offset: u64, info: u64, addend: i64) -> Self {
+        let mut rela = Self::default();
+        rela.set_offset(offset);
+        rela.set_info(info);
+        rela.set_addend(addend);
+
+        rela
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_offset(&mut self, offset: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, offset);
+        self.offset = buf;
+    }
+
+    pub fn set_info(&mut self, info: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, info);
+        self.info = buf;
+    }
+
+    pub fn set_addend(&mut self, addend: i64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_i64(&mut buf, addend);
+        self.addend = buf;
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf64Dyn {
+    tag: [u8; 8],
+    value: [u8; 8],
+}
+
+impl Elf64Dyn {
+    pub fn new(tag: i64, value: u64) -> Self {
+        let mut dynamic = Self::default();
+        dynamic.set_tag(tag);
+        dynamic.set_value(value);
+
+        dynamic
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_tag(&mut self, tag: i64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_i64(&mut buf, tag);
+        self.tag = buf;
+    }
+
+    pub fn set_value(&mut self, value: u64) {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, value);
+        self.value = buf;
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Elf32Header {
+    magic_nums: [u8; 4],
+    class: u8,
+    endian: u8,
+    version: u8,
+    abi: u8,
+    abi_version: u8,
+    reserved: [u8; 7],
+    object_type: [u8; 2],
+    machine_type: [u8; 2],
+    version2: [u8; 4],
+    entry: [u8; 4],
+    program_header_offset: [u8; 4],
+    section_header_offset: [u8; 4],
+    flags: [u8; 4],
+    header_size: [u8; 2],
+    program_header_size: [u8; 2],
+    program_header_num: [u8; 2],
+    section_header_size: [u8; 2],
+    section_header_num: [u8; 2],
+    section_header_str_index: [u8; 2],
+}
+
+impl Elf32Header {
+    pub fn template() -> Self {
+        Self {
+            magic_nums: MAGIC_NUMS,
+            class: 0x1,
+            endian: 0x1,
+            version: 0x1,
+            abi: 0x0,
+            abi_version: 0x0,
+            reserved: [0x0; 7],
+            object_type: ET_REL.to_le_bytes(),
+            machine_type: EM_386.to_le_bytes(),
+            version2: [0x1, 0x0, 0x0, 0x0],
+            entry: [0x0; 4],
+            program_header_offset: [0x0; 4],
+            section_header_offset: [0x34, 0x0, 0x0, 0x0],
+            flags: [0x0; 4],
+            header_size: [0x34, 0x0],
+            program_header_size: [0x0, 0x0],
+            program_header_num: [0x0, 0x0],
+            section_header_size: [0x28, 0x0],
+            section_header_num: [0x5, 0x0],
+            section_header_str_index: [0x2, 0x0],
+        }
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_section_header_num(&mut self, section_header_num: u16) {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, section_header_num);
+        self.section_header_num = buf;
+    }
+
+    pub fn set_section_header_str_index(&mut self, section_header_str_index: u16) {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, section_header_str_index);
+        self.section_header_str_index = buf;
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf32SectionHeader {
+    name: [u8; 4],
+    s_type: [u8; 4],
+    flags: [u8; 4],
+    addr: [u8; 4],
+    offset: [u8; 4],
+    size: [u8; 4],
+    link: [u8; 4],
+    info: [u8; 4],
+    align: [u8; 4],
+    entry_size: [u8; 4],
+}
+
+impl Elf32SectionHeader {
+    // same fields, narrowed
+    pub fn from_elf64(header: &Elf64SectionHeader) -> Self {
+        let mut header32 = Self::default();
+        header32.set_name(header.name());
+        header32.set_s_type(header.s_type());
+        header32.set_flags(header.flags() as u32);
+        header32.set_addr(header.addr() as u32);
+        header32.set_offset(header.offset() as u32);
+        header32.set_size(header.size() as u32);
+        header32.set_link(header.link());
+        header32.set_info(header.info());
+        header32.set_align(header.align() as u32);
+        header32.set_entry_size(header.entry_size() as u32);
+
+        header32
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_name(&mut self, name: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, name);
+        self.name = buf;
+    }
+
+    pub fn set_s_type(&mut self, s_type: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, s_type);
+        self.s_type = buf;
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, flags);
+        self.flags = buf;
+    }
+
+    pub fn set_addr(&mut self, addr: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, addr);
+        self.addr = buf;
+    }
+
+    pub fn set_offset(&mut self, offset: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, offset);
+        self.offset = buf;
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, size);
+        self.size = buf;
+    }
+
+    pub fn set_link(&mut self, link: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, link);
+        self.link = buf;
+    }
+
+    pub fn set_info(&mut self, info: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, info);
+        self.info = buf;
+    }
+
+    pub fn set_align(&mut self, align: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, align);
+        self.align = buf;
+    }
+
+    pub fn set_entry_size(&mut self, entry_size: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, entry_size);
+        self.entry_size = buf;
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf32SymbolTableSection {
+    name: [u8; 4],
+    value: [u8; 4],
+    size: [u8; 4],
+    info: u8,
+    other: u8,
+    index: [u8; 2],
+}
+
+impl Elf32SymbolTableSection {
+    // same fields, narrowed
+    pub fn from_elf64(symbol: &Elf64SymbolTableSection) -> Self {
+        let mut symbol32 = Self::default();
+        symbol32.set_name(symbol.name());
+        symbol32.set_value(symbol.value() as u32);
+        symbol32.set_size(symbol.size() as u32);
+        symbol32.set_info(symbol.info());
+        symbol32.set_other(symbol.other());
+        symbol32.set_index(symbol.index());
+
+        symbol32
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_name(&mut self, name: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, name);
+        self.name = buf;
+    }
+
+    pub fn set_value(&mut self, value: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, value);
+        self.value = buf;
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, size);
+        self.size = buf;
+    }
+
+    pub fn set_info(&mut self, info: u8) {
+        self.info = info;
+    }
+
+    pub fn set_other(&mut self, other: u8) {
+        self.other = other;
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, index);
+        self.index = buf;
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Elf32Rel {
+    offset: [u8; 4],
+    info: [u8; 4],
+}
+
+impl Elf32Rel {
+    pub fn new(offset: u32, info: u32) -> Self {
+        let mut rel = Self::default();
+        rel.set_offset(offset);
+        rel.set_info(info);
+
+        rel
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) }
+    }
+
+    pub fn set_offset(&mut self, offset: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, offset);
+        self.offset = buf;
+    }
+
+    pub fn set_info(&mut self, info: u32) {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, info);
+        self.info = buf;
     }
 }
@@ -0,0 +1,901 @@
+use crate::{
+    expr::Expr,
+    operand::{MemoryOperand, Register, RegisterKind},
+    parse::{Instruction, Mnemonic, Operand},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixupKind {
+    Absolute,
+    // sign-extended by the cpu
+    Signed,
+    // relative to the end of the instruction
+    Relative,
+}
+
+// value that can't be encoded until symbols are resolved
+#[derive(Debug, Clone)]
+pub struct Fixup {
+    pub offset: usize,
+    pub size: u8,
+    pub kind: FixupKind,
+    pub expr: Expr,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Encoding {
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+// value of an expression without any symbols
+pub fn constant(expr: &Expr) -> Option<i64> {
+    expr.eval(&|_| None)
+        .filter(|v| v.base.is_none())
+        .map(|v| v.offset)
+}
+
+// whether the value fits in "size" bytes, signed or unsigned
+pub fn fits(value: i64, size: u8) -> bool {
+    if size >= 8 {
+        return true;
+    }
+
+    let bits = size as u32 * 8;
+    value >= -(1 << (bits - 1)) && value < (1 << bits)
+}
+
+// write a resolved value into the field, false if it doesn't fit
+pub fn patch(bytes: &mut [u8], offset: u64, size: u8, kind: FixupKind, value: i64) -> bool {
+    let is_valid = match kind {
+        FixupKind::Absolute => fits(value, size),
+        // the cpu sign-extends these
+        FixupKind::Signed | FixupKind::Relative => {
+            size >= 8
+                || (-(1 << (size as u32 * 8 - 1))..(1 << (size as u32 * 8 - 1))).contains(&value)
+        }
+    };
+    if !is_valid {
+        return false;
+    }
+
+    let offset = offset as usize;
+    bytes[offset..offset + size as usize].copy_from_slice(&value.to_le_bytes()[..size as usize]);
+
+    true
+}
+
+fn fits_i8(expr: &Expr) -> bool {
+    matches!(constant(expr), Some(v) if (-128..128).contains(&v))
+}
+
+enum Rm<'a> {
+    Register(Register),
+    Memory(&'a MemoryOperand),
+}
+
+impl Rm<'_> {
+    fn from_operand(operand: &Operand) -> Option<Rm<'_>> {
+        match operand {
+            Operand::Register(r) if r.kind != RegisterKind::Segment => Some(Rm::Register(*r)),
+            Operand::Memory(m) => Some(Rm::Memory(m)),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> Option<u8> {
+        match self {
+            Rm::Register(r) => Some(r.size),
+            Rm::Memory(m) => m.size,
+        }
+    }
+}
+
+struct Encoder {
+    bits: u8,
+    encoding: Encoding,
+}
+
+impl Encoder {
+    fn push_value(&mut self, expr: &Expr, size: u8, kind: FixupKind) -> Option<()> {
+        match constant(expr) {
+            Some(value) if kind != FixupKind::Relative => {
+                if !fits(value, size) {
+                    return None;
+                }
+                self.encoding
+                    .bytes
+                    .extend(&value.to_le_bytes()[..size as usize]);
+            }
+            _ => {
+                self.encoding.fixups.push(Fixup {
+                    offset: self.encoding.bytes.len(),
+                    size,
+                    kind,
+                    expr: expr.clone(),
+                });
+                self.encoding.bytes.extend(vec![0x0; size as usize]);
+            }
+        }
+
+        Some(())
+    }
+
+    // sign-extended immediate of an operand size
+    fn push_immediate(&mut self, expr: &Expr, size: u8) -> Option<()> {
+        match size {
+            1 | 2 => self.push_value(expr, size, FixupKind::Absolute),
+            4 => self.push_value(expr, 4, FixupKind::Absolute),
+            _ => self.push_value(expr, 4, FixupKind::Signed),
+        }
+    }
+
+    fn operand_size_prefix(&mut self, size: u8) -> Option<bool> {
+        match (size, self.bits) {
+            (2, 32 | 64) | (4, 16) => self.encoding.bytes.push(0x66),
+            (8, 64) => return Some(true),
+            (8, _) => return None,
+            _ => (),
+        }
+
+        Some(false)
+    }
+
+    // register encoded in the low bits of the opcode, like push or bswap
+    fn emit_opcode_register(&mut self, opcode: u8, r: Register, size: u8) -> Option<()> {
+        let mut rex = 0x0;
+        if self.operand_size_prefix(size)? {
+            rex |= 0x48;
+        }
+        if r.is_extended() {
+            rex |= 0x41;
+        }
+
+        if rex != 0x0 || r.needs_rex() {
+            if self.bits != 64 || r.kind == RegisterKind::HighByte {
+                return None;
+            }
+            self.encoding.bytes.push(0x40 | rex);
+        }
+        self.encoding.bytes.push(opcode + r.low_bits());
+
+        Some(())
+    }
+
+    // "size" is the operand size, 0 when it's implied by the opcode
+    fn emit(
+        &mut self,
+        opcode: &[u8],
+        size: u8,
+        reg: Option<Register>,
+        digit: u8,
+        rm: Rm,
+        imm: Option<(&Expr, u8)>,
+    ) -> Option<()> {
+        let mut rex = 0x0;
+        let mut force_rex = false;
+        let mut high_byte = false;
+
+        if let Rm::Memory(m) = &rm {
+            if let Some(segment) = m.segment {
+                self.encoding
+                    .bytes
+                    .push([0x26, 0x2e, 0x36, 0x3e, 0x64, 0x65][segment.number as usize]);
+            }
+
+            match (self.address_size(m)?, self.bits) {
+                (8, 64) | (4, 32) | (2, 16) => (),
+                (4, 64) | (2, 32) | (4, 16) => self.encoding.bytes.push(0x67),
+                _ => return None,
+            }
+        }
+
+        if self.operand_size_prefix(size)? {
+            rex |= 0x48;
+        }
+
+        if let Some(r) = reg {
+            if r.is_extended() {
+                rex |= 0x44;
+            }
+            force_rex |= r.needs_rex();
+            high_byte |= r.kind == RegisterKind::HighByte;
+        }
+
+        let digit = reg.map(|r| r.low_bits()).unwrap_or(digit);
+        let mut modrm_bytes = Vec::new();
+        let mut disp = None;
+
+        match rm {
+            Rm::Register(r) => {
+                if r.is_extended() {
+                    rex |= 0x41;
+                }
+                force_rex |= r.needs_rex();
+                high_byte |= r.kind == RegisterKind::HighByte;
+                modrm_bytes.push(0xc0 | (digit << 3) | r.low_bits());
+            }
+            Rm::Memory(m) => {
+                if let Some((index, _)) = m.index {
+                    if index.low_bits() == 4 && !index.is_extended() {
+                        // rsp can't be an index
+                        return None;
+                    }
+                    if index.is_extended() {
+                        rex |= 0x42;
+                    }
+                }
+                if let Some(base) = m.base {
+                    if base.is_extended() {
+                        rex |= 0x41;
+                    }
+                }
+
+                let (bytes, d) = match self.address_size(m)? {
+                    2 => self.memory_modrm16(m, digit)?,
+                    _ => self.memory_modrm(m, digit)?,
+                };
+                modrm_bytes = bytes;
+                disp = d;
+            }
+        }
+
+        if rex != 0x0 || force_rex {
+            if high_byte || self.bits != 64 {
+                return None;
+            }
+            self.encoding.bytes.push(rex | 0x40);
+        }
+
+        self.encoding.bytes.extend(opcode);
+        self.encoding.bytes.extend(modrm_bytes);
+
+        if let Some((expr, size, kind)) = disp {
+            self.push_value(&expr, size, kind)?;
+        }
+
+        if let Some((expr, size)) = imm {
+            self.push_immediate(expr, size)?;
+        }
+
+        Some(())
+    }
+
+    // given by the registers, the mode's default without them
+    fn address_size(&self, m: &MemoryOperand) -> Option<u8> {
+        match (m.base, m.index) {
+            (Some(b), Some((i, _))) if b.size != i.size => None,
+            (Some(b), _) => Some(b.size),
+            (None, Some((i, _))) => Some(i.size),
+            (None, None) => Some(self.bits / 8),
+        }
+    }
+
+    // 16-bit addressing has a fixed set of base and index pairs
+    #[allow(clippy::type_complexity)]
+    fn memory_modrm16(
+        &self,
+        m: &MemoryOperand,
+        digit: u8,
+    ) -> Option<(Vec<u8>, Option<(Expr, u8, FixupKind)>)> {
+        if m.rel || matches!(m.index, Some((_, scale)) if scale != 1) {
+            return None;
+        }
+
+        let mut registers: Vec<u8> = m
+            .base
+            .iter()
+            .chain(m.index.iter().map(|(index, _)| index))
+            .map(|r| r.number)
+            .collect();
+        registers.sort();
+
+        let disp = m.disp.clone().unwrap_or(Expr::Number(0));
+        // bx = 3, bp = 5, si = 6, di = 7
+        let rm = match registers[..] {
+            [] => {
+                return Some((
+                    vec![(digit << 3) | 0x6],
+                    Some((disp, 2, FixupKind::Absolute)),
+                ));
+            }
+            [3, 6] => 0x0,
+            [3, 7] => 0x1,
+            [5, 6] => 0x2,
+            [5, 7] => 0x3,
+            [6] => 0x4,
+            [7] => 0x5,
+            [5] => 0x6,
+            [3] => 0x7,
+            _ => return None,
+        };
+
+        let (mode, disp) = match &m.disp {
+            // [bp] needs a displacement
+            None if rm != 0x6 => (0x0, None),
+            None => (0x1, Some((disp, 1, FixupKind::Absolute))),
+            Some(d) if fits_i8(d) => (0x1, Some((disp, 1, FixupKind::Absolute))),
+            Some(_) => (0x2, Some((disp, 2, FixupKind::Absolute))),
+        };
+
+        Some((vec![(mode << 6) | (digit << 3) | rm], disp))
+    }
+
+    // modrm, sib and displacement of a memory operand
+    #[allow(clippy::type_complexity)]
+    fn memory_modrm(
+        &self,
+        m: &MemoryOperand,
+        digit: u8,
+    ) -> Option<(Vec<u8>, Option<(Expr, u8, FixupKind)>)> {
+        let abs_kind = if self.bits == 64 {
+            FixupKind::Signed
+        } else {
+            FixupKind::Absolute
+        };
+        let disp = m.disp.clone().unwrap_or(Expr::Number(0));
+
+        if m.rel {
+            if m.base.is_some() || m.index.is_some() || self.bits != 64 {
+                return None;
+            }
+            return Some((
+                vec![(digit << 3) | 0x5],
+                Some((disp, 4, FixupKind::Relative)),
+            ));
+        }
+
+        let base = match m.base {
+            Some(base) => base,
+            None => {
+                return Some(match (m.index, self.address_size(m)?) {
+                    (Some((index, scale)), _) => (
+                        vec![(digit << 3) | 0x4, sib(scale, index.low_bits(), 0x5)],
+                        Some((disp, 4, abs_kind)),
+                    ),
+                    (None, 8) => (vec![(digit << 3) | 0x4, 0x25], Some((disp, 4, abs_kind))),
+                    (None, _) => (vec![(digit << 3) | 0x5], Some((disp, 4, abs_kind))),
+                });
+            }
+        };
+
+        let (mode, disp) = match &m.disp {
+            None if base.low_bits() != 0x5 => (0x0, None),
+            None => (0x1, Some((disp, 1, FixupKind::Absolute))),
+            Some(d) if fits_i8(d) => (0x1, Some((disp, 1, FixupKind::Absolute))),
+            Some(_) => (0x2, Some((disp, 4, abs_kind))),
+        };
+
+        if m.index.is_none() && base.low_bits() != 0x4 {
+            return Some((vec![(mode << 6) | (digit << 3) | base.low_bits()], disp));
+        }
+
+        let (index, scale) = match m.index {
+            Some((index, scale)) => (index.low_bits(), scale),
+            None => (0x4, 1),
+        };
+
+        Some((
+            vec![
+                (mode << 6) | (digit << 3) | 0x4,
+                sib(scale, index, base.low_bits()),
+            ],
+            disp,
+        ))
+    }
+
+    // "op r/m, imm" group like add or cmp
+    fn emit_alu(&mut self, n: u8, ops: &[Operand]) -> Option<()> {
+        match ops {
+            [Operand::Register(r1), Operand::Register(r2)] if r1.size == r2.size => {
+                let opcode = if r1.size == 1 { 0x00 } else { 0x01 } + n * 8;
+                self.emit(&[opcode], r1.size, Some(*r2), 0, Rm::Register(*r1), None)
+            }
+            [Operand::Register(r), Operand::Memory(m)] if m.size.unwrap_or(r.size) == r.size => {
+                let opcode = if r.size == 1 { 0x02 } else { 0x03 } + n * 8;
+                self.emit(&[opcode], r.size, Some(*r), 0, Rm::Memory(m), None)
+            }
+            [Operand::Memory(m), Operand::Register(r)] if m.size.unwrap_or(r.size) == r.size => {
+                let opcode = if r.size == 1 { 0x00 } else { 0x01 } + n * 8;
+                self.emit(&[opcode], r.size, Some(*r), 0, Rm::Memory(m), None)
+            }
+            [rm, Operand::Immediate(imm)] => {
+                let rm = Rm::from_operand(rm)?;
+                let size = rm.size()?;
+
+                if size == 1 {
+                    if let Rm::Register(r) = rm {
+                        if r.number == 0 && r.is_general() {
+                            self.encoding.bytes.push(0x04 + n * 8);
+                            return self.push_value(imm, 1, FixupKind::Absolute);
+                        }
+                    }
+                    return self.emit(&[0x80], 1, None, n, rm, Some((imm, 1)));
+                }
+
+                if fits_i8(imm) {
+                    self.emit(&[0x83], size, None, n, rm, None)?;
+                    return self.push_value(imm, 1, FixupKind::Absolute);
+                }
+
+                if let Rm::Register(r) = rm {
+                    if r.number == 0 {
+                        // short form for the accumulator
+                        if self.operand_size_prefix(size)? {
+                            self.encoding.bytes.push(0x48);
+                        }
+                        self.encoding.bytes.push(0x05 + n * 8);
+                        return self.push_immediate(imm, size);
+                    }
+                }
+
+                self.emit(&[0x81], size, None, n, rm, Some((imm, size)))
+            }
+            _ => None,
+        }
+    }
+
+    // "op r/m" group like not or div
+    fn emit_unary(&mut self, opcode: u8, n: u8, ops: &[Operand]) -> Option<()> {
+        let rm = match ops {
+            [rm] => Rm::from_operand(rm)?,
+            _ => return None,
+        };
+        let size = rm.size()?;
+        let opcode = if size == 1 { opcode } else { opcode + 1 };
+
+        self.emit(&[opcode], size, None, n, rm, None)
+    }
+
+    fn emit_shift(&mut self, n: u8, ops: &[Operand]) -> Option<()> {
+        let (rm, count) = match ops {
+            [rm, count] => (Rm::from_operand(rm)?, count),
+            _ => return None,
+        };
+        let size = rm.size()?;
+        let w = if size == 1 { 0 } else { 1 };
+
+        match count {
+            Operand::Immediate(imm) if constant(imm) == Some(1) => {
+                self.emit(&[0xd0 + w], size, None, n, rm, None)
+            }
+            Operand::Register(r) if r.is_general() && r.size == 1 && r.number == 1 => {
+                self.emit(&[0xd2 + w], size, None, n, rm, None)
+            }
+            Operand::Immediate(imm) => {
+                self.emit(&[0xc0 + w], size, None, n, rm, None)?;
+                self.push_value(imm, 1, FixupKind::Absolute)
+            }
+            _ => None,
+        }
+    }
+
+    // call, jmp and jcc
+    fn emit_branch(
+        &mut self,
+        near: &[u8],
+        short: Option<u8>,
+        n: u8,
+        ops: &[Operand],
+    ) -> Option<()> {
+        match ops {
+            [Operand::Immediate(target)] => {
+                self.encoding.bytes.extend(near);
+                let size = if self.bits == 16 { 2 } else { 4 };
+                self.push_value(target, size, FixupKind::Relative)
+            }
+            // direct far jump and call, not in 64-bit mode
+            [Operand::Far(segment, offset)] if n != 0 && self.bits != 64 => {
+                self.encoding.bytes.push(if n == 2 { 0x9a } else { 0xea });
+                self.push_value(offset, self.bits / 8, FixupKind::Absolute)?;
+                self.push_value(segment, 2, FixupKind::Absolute)
+            }
+            [Operand::SizedImmediate(1, target)] => {
+                self.encoding.bytes.push(short?);
+                self.push_value(target, 1, FixupKind::Relative)
+            }
+            [rm] if n != 0 => {
+                let rm = Rm::from_operand(rm)?;
+                // operand size is the address size
+                if !matches!(rm.size(), Some(size) if size * 8 == self.bits) && rm.size().is_some()
+                {
+                    return None;
+                }
+                self.emit(&[0xff], 0, None, n, rm, None)
+            }
+            _ => None,
+        }
+    }
+
+    fn emit_push_pop(&mut self, is_push: bool, ops: &[Operand]) -> Option<()> {
+        let stack_size = self.bits / 8;
+        // operand sizes other than the stack size need a prefix
+        let is_valid_size = |size: u8| match self.bits {
+            64 => size == 8 || size == 2,
+            _ => size == 4 || size == 2,
+        };
+
+        match ops {
+            [Operand::Register(r)] if r.is_general() && is_valid_size(r.size) => {
+                let opcode = if is_push { 0x50 } else { 0x58 };
+                let size = if r.size == stack_size { 0 } else { r.size };
+                return self.emit_opcode_register(opcode, *r, size);
+            }
+            // es, cs, ss and ds, there is no "pop cs"
+            [Operand::Register(r)]
+                if r.kind == RegisterKind::Segment
+                    && r.number < 4
+                    && self.bits != 64
+                    && (is_push || r.number != 1) =>
+            {
+                let opcode = if is_push { 0x06 } else { 0x07 };
+                self.encoding.bytes.push(opcode + r.number * 8);
+            }
+            [Operand::Register(r)] if r.kind == RegisterKind::Segment && r.number >= 4 => {
+                // fs and gs
+                let opcode = match (is_push, r.number) {
+                    (true, 4) => 0xa0,
+                    (false, 4) => 0xa1,
+                    (true, _) => 0xa8,
+                    (false, _) => 0xa9,
+                };
+                self.encoding.bytes.extend([0x0f, opcode]);
+            }
+            [Operand::Memory(m)] => {
+                let size = match m.size {
+                    Some(size) if size == stack_size => 0,
+                    Some(size) if is_valid_size(size) => size,
+                    None => 0,
+                    _ => return None,
+                };
+                let (opcode, n) = if is_push { (0xff, 6) } else { (0x8f, 0) };
+                return self.emit(&[opcode], size, None, n, Rm::Memory(m), None);
+            }
+            [Operand::Immediate(imm)] if is_push => {
+                if fits_i8(imm) {
+                    self.encoding.bytes.push(0x6a);
+                    return self.push_value(imm, 1, FixupKind::Absolute);
+                }
+                self.encoding.bytes.push(0x68);
+                return self.push_immediate(imm, stack_size);
+            }
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    fn emit_mov(&mut self, ops: &[Operand]) -> Option<()> {
+        match ops {
+            [Operand::Register(r1), Operand::Register(r2)]
+                if r1.kind != RegisterKind::Segment && r2.kind != RegisterKind::Segment =>
+            {
+                if r1.size != r2.size {
+                    return None;
+                }
+                let opcode = if r1.size == 1 { 0x88 } else { 0x89 };
+                self.emit(&[opcode], r1.size, Some(*r2), 0, Rm::Register(*r1), None)
+            }
+            [Operand::Register(s), rm] if s.kind == RegisterKind::Segment => {
+                let rm = Rm::from_operand(rm)?;
+                if !matches!(rm.size(), None | Some(2)) {
+                    return None;
+                }
+                self.emit(&[0x8e], 0, None, s.number, rm, None)
+            }
+            [rm, Operand::Register(s)] if s.kind == RegisterKind::Segment => {
+                let rm = Rm::from_operand(rm)?;
+                if !matches!(rm.size(), None | Some(2)) {
+                    return None;
+                }
+                self.emit(&[0x8c], 0, None, s.number, rm, None)
+            }
+            [Operand::Register(r), Operand::Memory(m)] if m.size.unwrap_or(r.size) == r.size => {
+                let opcode = if r.size == 1 { 0x8a } else { 0x8b };
+                self.emit(&[opcode], r.size, Some(*r), 0, Rm::Memory(m), None)
+            }
+            [Operand::Memory(m), Operand::Register(r)] if m.size.unwrap_or(r.size) == r.size => {
+                let opcode = if r.size == 1 { 0x88 } else { 0x89 };
+                self.emit(&[opcode], r.size, Some(*r), 0, Rm::Memory(m), None)
+            }
+            [Operand::Register(r), Operand::Immediate(imm)] if r.is_general() || r.size == 1 => {
+                if r.size == 8 && self.bits != 64 {
+                    return None;
+                }
+
+                let size = match (r.size, constant(imm)) {
+                    // same as nasm, zero-extended 32-bit move is shorter
+                    (8, Some(v)) if (0..=u32::MAX as i64).contains(&v) => 4,
+                    (8, Some(v)) if fits(v, 4) => {
+                        return self.emit(&[0xc7], 8, None, 0, Rm::Register(*r), Some((imm, 8)));
+                    }
+                    (size, _) => size,
+                };
+
+                let opcode = if size == 1 { 0xb0 } else { 0xb8 };
+                self.emit_opcode_register(opcode, *r, size)?;
+                self.push_value(imm, size, FixupKind::Absolute)
+            }
+            [Operand::Memory(m), Operand::Immediate(imm)] => {
+                let size = m.size?;
+                let opcode = if size == 1 { 0xc6 } else { 0xc7 };
+                self.emit(&[opcode], size, None, 0, Rm::Memory(m), Some((imm, size)))
+            }
+            _ => None,
+        }
+    }
+
+    fn emit_data(&mut self, size: u8, ops: &[Operand]) -> Option<()> {
+        for operand in ops {
+            match operand {
+                Operand::Immediate(expr) => self.push_value(expr, size, FixupKind::Absolute)?,
+                Operand::String(string) => {
+                    self.encoding.bytes.extend(string);
+                    // strings are padded to a multiple of the unit size
+                    let len = string.len() % size as usize;
+                    if len != 0 {
+                        self.encoding.bytes.extend(vec![0x0; size as usize - len]);
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        Some(())
+    }
+}
+
+fn sib(scale: u8, index: u8, base: u8) -> u8 {
+    let scale = match scale {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        _ => 3,
+    };
+
+    (scale << 6) | (index << 3) | base
+}
+
+// recommended multi-byte nops, "nop" with more prefixes and longer modrm
+const NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+// as few nops as possible filling "size" bytes of code
+pub fn nop_padding(size: u64, bits: u8) -> Vec<u8> {
+    // no sib byte with 16-bit addressing
+    if bits == 16 {
+        return vec![0x90; size as usize];
+    }
+
+    let mut bytes = Vec::new();
+    let mut left = size as usize;
+    while left != 0 {
+        let nop = NOPS[left.min(NOPS.len()) - 1];
+        bytes.extend(nop);
+        left -= nop.len();
+    }
+
+    bytes
+}
+
+// single repetition of an instruction, "times" is not applied
+pub fn encode(ins: &Instruction, bits: u8) -> Option<Encoding> {
+    let mut e = Encoder {
+        bits,
+        encoding: Encoding::default(),
+    };
+    let ops = &ins.operands[..];
+
+    match (&ins.mnemonic, ops) {
+        (Mnemonic::Db, _) => e.emit_data(1, ops)?,
+        (Mnemonic::Dw, _) => e.emit_data(2, ops)?,
+        (Mnemonic::Dd, _) => e.emit_data(4, ops)?,
+        (Mnemonic::Dq, _) => e.emit_data(8, ops)?,
+        (Mnemonic::Resb | Mnemonic::Resw | Mnemonic::Resd | Mnemonic::Resq, _) => {
+            // the zeros are only written out by SectionNode::encode
+            ins.reserve_count()?;
+        }
+        (Mnemonic::Add, _) => e.emit_alu(0, ops)?,
+        (Mnemonic::Or, _) => e.emit_alu(1, ops)?,
+        (Mnemonic::Adc, _) => e.emit_alu(2, ops)?,
+        (Mnemonic::Sbb, _) => e.emit_alu(3, ops)?,
+        (Mnemonic::And, _) => e.emit_alu(4, ops)?,
+        (Mnemonic::Sub, _) => e.emit_alu(5, ops)?,
+        (Mnemonic::Xor, _) => e.emit_alu(6, ops)?,
+        (Mnemonic::Cmp, _) => e.emit_alu(7, ops)?,
+        (
+            Mnemonic::Test,
+            [rm, Operand::Register(r)] | [Operand::Register(r), rm @ Operand::Memory(_)],
+        ) => {
+            let rm = Rm::from_operand(rm)?;
+            if rm.size().unwrap_or(r.size) != r.size {
+                return None;
+            }
+            let opcode = if r.size == 1 { 0x84 } else { 0x85 };
+            e.emit(&[opcode], r.size, Some(*r), 0, rm, None)?;
+        }
+        (Mnemonic::Test, [rm, Operand::Immediate(imm)]) => {
+            let rm = Rm::from_operand(rm)?;
+            let size = rm.size()?;
+            let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+            e.emit(&[opcode], size, None, 0, rm, Some((imm, size)))?;
+        }
+        // short forms, these are REX prefixes in 64-bit mode
+        (Mnemonic::Inc | Mnemonic::Dec, [Operand::Register(r)])
+            if bits != 64 && r.is_general() && r.size != 1 =>
+        {
+            let opcode = if ins.mnemonic == Mnemonic::Inc {
+                0x40
+            } else {
+                0x48
+            };
+            e.emit_opcode_register(opcode, *r, r.size)?;
+        }
+        (Mnemonic::Inc, _) => e.emit_unary(0xfe, 0, ops)?,
+        (Mnemonic::Dec, _) => e.emit_unary(0xfe, 1, ops)?,
+        (Mnemonic::Cqo, []) if bits != 64 => return None,
+        (Mnemonic::Not, _) => e.emit_unary(0xf6, 2, ops)?,
+        (Mnemonic::Neg, _) => e.emit_unary(0xf6, 3, ops)?,
+        (Mnemonic::Mul, _) => e.emit_unary(0xf6, 4, ops)?,
+        (Mnemonic::Imul, [_]) => e.emit_unary(0xf6, 5, ops)?,
+        (Mnemonic::Imul, [Operand::Register(r), rm]) => {
+            let rm = Rm::from_operand(rm)?;
+            if r.size == 1 || rm.size().unwrap_or(r.size) != r.size {
+                return None;
+            }
+            e.emit(&[0x0f, 0xaf], r.size, Some(*r), 0, rm, None)?;
+        }
+        (Mnemonic::Imul, [Operand::Register(r), rm, Operand::Immediate(imm)]) => {
+            let rm = Rm::from_operand(rm)?;
+            if r.size == 1 || rm.size().unwrap_or(r.size) != r.size {
+                return None;
+            }
+            if fits_i8(imm) {
+                e.emit(&[0x6b], r.size, Some(*r), 0, rm, None)?;
+                e.push_value(imm, 1, FixupKind::Absolute)?;
+            } else {
+                e.emit(&[0x69], r.size, Some(*r), 0, rm, Some((imm, r.size)))?;
+            }
+        }
+        (Mnemonic::Div, _) => e.emit_unary(0xf6, 6, ops)?,
+        (Mnemonic::Idiv, _) => e.emit_unary(0xf6, 7, ops)?,
+        (Mnemonic::Rol, _) => e.emit_shift(0, ops)?,
+        (Mnemonic::Ror, _) => e.emit_shift(1, ops)?,
+        (Mnemonic::Rcl, _) => e.emit_shift(2, ops)?,
+        (Mnemonic::Rcr, _) => e.emit_shift(3, ops)?,
+        (Mnemonic::Shl, _) => e.emit_shift(4, ops)?,
+        (Mnemonic::Shr, _) => e.emit_shift(5, ops)?,
+        (Mnemonic::Sar, _) => e.emit_shift(7, ops)?,
+        (Mnemonic::Mov, _) => e.emit_mov(ops)?,
+        (Mnemonic::Movzx | Mnemonic::Movsx, [Operand::Register(r), rm]) => {
+            let rm = Rm::from_operand(rm)?;
+            let opcode = match (&ins.mnemonic, rm.size()?) {
+                (Mnemonic::Movzx, 1) => 0xb6,
+                (Mnemonic::Movzx, 2) => 0xb7,
+                (Mnemonic::Movsx, 1) => 0xbe,
+                (Mnemonic::Movsx, 2) => 0xbf,
+                _ => return None,
+            };
+            if r.size <= rm.size()? {
+                return None;
+            }
+            e.emit(&[0x0f, opcode], r.size, Some(*r), 0, rm, None)?;
+        }
+        (Mnemonic::Movsxd, [Operand::Register(r), rm]) => {
+            let rm = Rm::from_operand(rm)?;
+            if r.size != 8 || rm.size()? != 4 {
+                return None;
+            }
+            e.emit(&[0x63], 8, Some(*r), 0, rm, None)?;
+        }
+        (Mnemonic::Lea, [Operand::Register(r), Operand::Memory(m)]) if r.size > 1 => {
+            e.emit(&[0x8d], r.size, Some(*r), 0, Rm::Memory(m), None)?;
+        }
+        (Mnemonic::Push, _) => e.emit_push_pop(true, ops)?,
+        (Mnemonic::Pop, _) => e.emit_push_pop(false, ops)?,
+        (Mnemonic::Call, _) => e.emit_branch(&[0xe8], None, 2, ops)?,
+        (Mnemonic::Jmp, _) => e.emit_branch(&[0xe9], Some(0xeb), 4, ops)?,
+        (Mnemonic::Jcc(cc), _) => e.emit_branch(&[0x0f, 0x80 + cc], Some(0x70 + cc), 0, ops)?,
+        (Mnemonic::Setcc(cc), [rm]) => {
+            let rm = Rm::from_operand(rm)?;
+            if rm.size().unwrap_or(1) != 1 {
+                return None;
+            }
+            e.emit(&[0x0f, 0x90 + cc], 0, None, 0, rm, None)?;
+        }
+        (Mnemonic::Cmovcc(cc), [Operand::Register(r), rm]) => {
+            let rm = Rm::from_operand(rm)?;
+            if r.size == 1 || rm.size().unwrap_or(r.size) != r.size {
+                return None;
+            }
+            e.emit(&[0x0f, 0x40 + cc], r.size, Some(*r), 0, rm, None)?;
+        }
+        (Mnemonic::Ret, [Operand::Immediate(imm)]) => {
+            e.encoding.bytes.push(0xc2);
+            e.push_value(imm, 2, FixupKind::Absolute)?;
+        }
+        (Mnemonic::Int, [Operand::Immediate(imm)]) => {
+            e.encoding.bytes.push(0xcd);
+            e.push_value(imm, 1, FixupKind::Absolute)?;
+        }
+        (mnemonic, []) => {
+            let opcode = mnemonic.get_opcode();
+            if opcode.is_empty() {
+                return None;
+            }
+            e.encoding.bytes.extend(opcode);
+        }
+        _ => return None,
+    }
+
+    Some(e.encoding)
+}
+
+#[test]
+fn test_encode() {
+    use crate::parse::{parse, LineToken};
+
+    let bytes = |line: &str| match parse(line) {
+        LineToken::Instruction(ins) => encode(&ins, 64).unwrap().bytes,
+        token => panic!("{:?}", token),
+    };
+
+    assert_eq!(bytes("mov rax, 60"), [0xb8, 0x3c, 0x00, 0x00, 0x00]);
+    assert_eq!(
+        bytes("mov rax, -1"),
+        [0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(bytes("add r8, rsi"), [0x49, 0x01, 0xf0]);
+    assert_eq!(bytes("mov sil, 1"), [0x40, 0xb6, 0x01]);
+    assert_eq!(
+        bytes("lea rdx, [rbx+rcx*8+16]"),
+        [0x48, 0x8d, 0x54, 0xcb, 0x10]
+    );
+    assert_eq!(bytes("mov eax, [rbp]"), [0x8b, 0x45, 0x00]);
+    assert_eq!(bytes("push r12"), [0x41, 0x54]);
+    assert_eq!(bytes("shl qword [rsp], 4"), [0x48, 0xc1, 0x24, 0x24, 0x04]);
+
+    let bytes32 = |line: &str| match parse(line) {
+        LineToken::Instruction(ins) => encode(&ins, 32),
+        token => panic!("{:?}", token),
+    };
+
+    assert_eq!(bytes32("inc eax").unwrap().bytes, [0x40]);
+    assert_eq!(bytes32("mov ax, [ebx]").unwrap().bytes, [0x66, 0x8b, 0x03]);
+    assert!(bytes32("mov rax, 1").is_none());
+    assert!(bytes32("push r8").is_none());
+
+    let bytes16 = |line: &str| match parse(line) {
+        LineToken::Instruction(ins) => encode(&ins, 16).unwrap().bytes,
+        token => panic!("{:?}", token),
+    };
+
+    assert_eq!(bytes16("mov al, [bx+si]"), [0x8a, 0x00]);
+    assert_eq!(bytes16("mov [bp], ax"), [0x89, 0x46, 0x00]);
+    assert_eq!(bytes16("mov eax, [esi+4]"), [0x67, 0x66, 0x8b, 0x46, 0x04]);
+    assert_eq!(bytes16("jmp 0x1234:0x10"), [0xea, 0x10, 0x00, 0x34, 0x12]);
+    assert_eq!(bytes16("endbr64"), [0xf3, 0x0f, 0x1e, 0xfa]);
+    assert_eq!(bytes32("endbr32").unwrap().bytes, [0xf3, 0x0f, 0x1e, 0xfb]);
+
+    let encoding = match parse("call func") {
+        LineToken::Instruction(ins) => encode(&ins, 64).unwrap(),
+        token => panic!("{:?}", token),
+    };
+    assert_eq!(encoding.bytes, [0xe8, 0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(encoding.fixups[0].offset, 1);
+    assert_eq!(encoding.fixups[0].kind, FixupKind::Relative);
+
+    assert_eq!(nop_padding(3, 64), [0x0f, 0x1f, 0x00]);
+    assert_eq!(nop_padding(10, 32).len(), 10);
+    assert_eq!(nop_padding(10, 32)[9], 0x90);
+    assert_eq!(nop_padding(2, 16), [0x90, 0x90]);
+}
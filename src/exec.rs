@@ -0,0 +1,625 @@
+use std::{
+    fs::{File, Permissions},
+    io::Write,
+    mem::size_of,
+    path::Path,
+};
+
+use crate::{
+    dwarf::{add_debug_sections, add_eh_frame, add_eh_frame_hdr, eh_frame_hdr},
+    elf::*,
+    expr::Expr,
+    generator::{
+        align_up, common_symbol_layout, gen_program, label_symbols, link_section, merge_sections,
+        parse_file,
+    },
+    node::{LabelNode, ProgramNode, SectionNode, SymbolAttributes},
+    note::{add_build_id_note, add_property_note, fill_build_id, BuildId},
+    parse::{Instruction, Mnemonic, Operand, SymbolType},
+    strtab::StringTable,
+};
+
+// same as ld
+const BASE_ADDRESS: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+
+pub fn reserve(bytes: u64) -> Instruction {
+    Instruction {
+        mnemonic: Mnemonic::Resb,
+        operands: vec![Operand::Immediate(Expr::Number(bytes as i64))],
+        times: 1,
+        repeat: None,
+        bits: 64,
+        line: 0,
+        file: 0,
+    }
+}
+
+// there is no linker to do this, so put common symbols in .bss
+pub fn allocate_common_symbols(program: &mut ProgramNode) {
+    for (name, size, align) in std::mem::take(&mut program.common_symbols) {
+        let (size, align) = common_symbol_layout(&name, &size, &align);
+
+        let section_index = match program.section_nodes.iter().position(|s| s.name == ".bss") {
+            Some(i) => i,
+            None => {
+                program
+                    .section_nodes
+                    .push(SectionNode::new(".bss".to_string()));
+                program.section_nodes.len() - 1
+            }
+        };
+
+        let bss = &mut program.section_nodes[section_index];
+        bss.attributes.align = bss.attributes.align.max(align);
+        let padding = align_up(bss.size, align) - bss.size;
+        if padding != 0 {
+            bss.push_instruction(reserve(padding));
+        }
+
+        let offset = bss.size;
+        bss.push_instruction(reserve(size));
+        if !program.push_label(LabelNode {
+            name: name.clone(),
+            section_index,
+            offset,
+        }) {
+            panic!("Common symbol \"{}\" is already defined", name);
+        }
+
+        let attributes = program
+            .symbol_attributes
+            .entry(name)
+            .or_insert_with(SymbolAttributes::new);
+        attributes.is_global = true;
+        attributes.s_type = SymbolType::Object;
+    }
+    // they are labels now
+    program.index_external_symbols();
+}
+
+// which PT_LOAD a section goes in, none if it's not loaded
+fn segment_index(section: &SectionNode) -> Option<usize> {
+    let flags = section.attributes.flags;
+
+    if flags & SHF_ALLOC == 0 {
+        return None;
+    }
+
+    if flags & SHF_EXECINSTR != 0 {
+        return Some(1);
+    }
+
+    if flags & SHF_WRITE != 0 {
+        return Some(2);
+    }
+
+    Some(0)
+}
+
+// where the sections of an image go, in memory and in the file
+pub struct ImageLayout {
+    pub section_addresses: Vec<u64>,
+    pub section_offsets: Vec<u64>,
+    pub program_headers: Vec<Elf64ProgramHeader>,
+    // end of the sections in the file
+    pub size: u64,
+}
+
+// PT_LOAD for each segment in use, PT_GNU_STACK, PT_NOTE for each loaded note
+// and PT_GNU_EH_FRAME, with room for extra program headers
+pub fn layout_image(program: &ProgramNode, base_address: u64, extra_headers: usize) -> ImageLayout {
+    let section_num = program.section_nodes.len();
+    // PT_TLS and the thread pointer offsets are up to a linker
+    if let Some(s) = program
+        .section_nodes
+        .iter()
+        .find(|s| s.attributes.flags & SHF_TLS != 0)
+    {
+        panic!("Thread-local section \"{}\" needs a linker", s.name);
+    }
+    // read-only, executable and writable
+    let segment_flags = [PF_R, PF_R | PF_X, PF_R | PF_W];
+    let used_segments: Vec<usize> = (0..3)
+        .filter(|segment| {
+            // the first one also holds the headers
+            *segment == 0
+                || program
+                    .section_nodes
+                    .iter()
+                    .any(|s| segment_index(s) == Some(*segment))
+        })
+        .collect();
+
+    // non-executable stack unless .note.GNU-stack says otherwise
+    let stack_flags = match program
+        .section_nodes
+        .iter()
+        .find(|s| s.name == ".note.GNU-stack")
+    {
+        Some(s) if s.attributes.flags & SHF_EXECINSTR != 0 => PF_R | PF_W | PF_X,
+        _ => PF_R | PF_W,
+    };
+
+    // loaded notes, .note.gnu.property also gets PT_GNU_PROPERTY
+    let notes: Vec<usize> = (0..section_num)
+        .filter(|i| {
+            let section = &program.section_nodes[*i];
+            section.attributes.s_type == SHT_NOTE && section.attributes.flags & SHF_ALLOC != 0
+        })
+        .collect();
+    let property_note = notes
+        .iter()
+        .find(|i| program.section_nodes[**i].name == ".note.gnu.property");
+
+    let eh_frame_hdr = (0..section_num).find(|i| program.section_nodes[*i].name == ".eh_frame_hdr");
+
+    let program_header_num = used_segments.len()
+        + 1
+        + notes.len()
+        + property_note.is_some() as usize
+        + eh_frame_hdr.is_some() as usize
+        + extra_headers;
+    let mut offset =
+        (size_of::<Elf64Header>() + size_of::<Elf64ProgramHeader>() * program_header_num) as u64;
+    let mut address = base_address + offset;
+
+    let mut section_addresses = vec![0; section_num];
+    let mut section_offsets = vec![0; section_num];
+    let mut program_headers = Vec::new();
+
+    for segment in used_segments {
+        let mut sections: Vec<usize> = (0..section_num)
+            .filter(|i| segment_index(&program.section_nodes[*i]) == Some(segment))
+            .collect();
+        // nobits sections only take memory, so they go last, notes go first after the headers
+        sections.sort_by_key(|i| {
+            let section = &program.section_nodes[*i];
+            (section.is_nobits(), section.attributes.s_type != SHT_NOTE)
+        });
+
+        let (start_offset, start_address) = if segment == 0 {
+            (0, base_address)
+        } else {
+            offset = align_up(offset, PAGE_SIZE);
+            address = align_up(address, PAGE_SIZE);
+            (offset, address)
+        };
+
+        for i in sections {
+            let section = &program.section_nodes[i];
+            address = align_up(address, section.attributes.align.max(1));
+            if !section.is_nobits() {
+                offset = start_offset + (address - start_address);
+            }
+
+            section_addresses[i] = address;
+            section_offsets[i] = offset;
+            address += section.size;
+            if !section.is_nobits() {
+                offset += section.size;
+            }
+        }
+
+        program_headers.push(Elf64ProgramHeader::new(
+            PT_LOAD,
+            segment_flags[segment],
+            start_offset,
+            start_address,
+            start_address,
+            offset - start_offset,
+            address - start_address,
+            PAGE_SIZE,
+        ));
+    }
+
+    program_headers.push(Elf64ProgramHeader::new(
+        PT_GNU_STACK,
+        stack_flags,
+        0,
+        0,
+        0,
+        0,
+        0,
+        16,
+    ));
+
+    let note_header = |p_type: u32, i: usize| {
+        Elf64ProgramHeader::new(
+            p_type,
+            PF_R,
+            section_offsets[i],
+            section_addresses[i],
+            section_addresses[i],
+            program.section_nodes[i].size,
+            program.section_nodes[i].size,
+            program.section_nodes[i].attributes.align,
+        )
+    };
+    for i in notes.iter() {
+        program_headers.push(note_header(PT_NOTE, *i));
+    }
+    if let Some(i) = property_note {
+        program_headers.push(note_header(PT_GNU_PROPERTY, *i));
+    }
+    if let Some(i) = eh_frame_hdr {
+        program_headers.push(note_header(PT_GNU_EH_FRAME, i));
+    }
+
+    // sections that are not loaded
+    for (i, section) in program.section_nodes.iter().enumerate() {
+        if segment_index(section).is_none() {
+            offset = align_up(offset, section.attributes.align.max(1));
+            section_offsets[i] = offset;
+            if !section.is_nobits() {
+                offset += section.size;
+            }
+        }
+    }
+
+    ImageLayout {
+        section_addresses,
+        section_offsets,
+        program_headers,
+        size: offset,
+    }
+}
+
+// .eh_frame_hdr from the linked .eh_frame, if there are both
+pub fn fill_eh_frame_hdr(program: &ProgramNode, layout: &ImageLayout, bytes: &mut [u8]) {
+    let section_index = |name: &str| program.section_nodes.iter().position(|s| s.name == name);
+    if let (Some(eh_frame), Some(hdr)) =
+        (section_index(".eh_frame"), section_index(".eh_frame_hdr"))
+    {
+        let start = layout.section_offsets[eh_frame] as usize;
+        let end = start + program.section_nodes[eh_frame].size as usize;
+        let data = eh_frame_hdr(
+            &bytes[start..end],
+            layout.section_addresses[eh_frame],
+            layout.section_addresses[hdr],
+        );
+        let start = layout.section_offsets[hdr] as usize;
+        bytes[start..start + data.len()].copy_from_slice(&data);
+    }
+}
+
+// adds .symtab and the headers to the linked sections and writes the file
+pub fn write_image(
+    program: &ProgramNode,
+    input_filepath: &Path,
+    output_filepath: &Path,
+    object_type: u16,
+    entry_address: u64,
+    layout: &ImageLayout,
+    mut bytes: Vec<u8>,
+) -> File {
+    let section_addresses = &layout.section_addresses;
+    // extended section numbering is only done for objects
+    if program.section_nodes.len() + 4 >= SHN_LORESERVE as usize {
+        panic!("Too many sections for an image");
+    }
+
+    // symbols are kept for debuggers, named by string table ids until it's built
+    let mut symbol_table = vec![Elf64SymbolTableSection::default()];
+    let mut string_table = StringTable::new();
+
+    symbol_table.push(Elf64SymbolTableSection::new(
+        string_table.add(input_filepath.to_str().unwrap()),
+        st_info(STB_LOCAL, STT_FILE),
+        0,
+        SHN_ABS,
+        0,
+        0,
+    ));
+
+    let (local_symbols, global_symbols) = label_symbols(program, &mut string_table, &|label| {
+        (
+            label.section_index + 1,
+            section_addresses[label.section_index] + label.offset,
+        )
+    });
+    symbol_table.extend(local_symbols.into_iter().map(|(_, symbol, _)| symbol));
+    let first_global_index = symbol_table.len();
+    symbol_table.extend(global_symbols.into_iter().map(|(_, symbol, _)| symbol));
+
+    let mut section_headers = vec![Elf64SectionHeader::default()];
+    let mut section_header_string_table = StringTable::new();
+
+    for (i, section_node) in program.section_nodes.iter().enumerate() {
+        let attributes = &section_node.attributes;
+        let link = match &attributes.link {
+            Some(name) => match program.section_nodes.iter().position(|s| s.name == *name) {
+                Some(index) => index + 1,
+                None => panic!("Section \"{}\" is not defined", name),
+            },
+            None => 0,
+        };
+        section_headers.push(Elf64SectionHeader::new(
+            section_header_string_table.add(&section_node.name),
+            attributes.s_type,
+            attributes.flags,
+            section_addresses[i],
+            layout.section_offsets[i],
+            section_node.size,
+            link as u32,
+            attributes.info,
+            attributes.align,
+            attributes.entry_size,
+        ));
+    }
+
+    let shstrtab_index = section_headers.len();
+    let strtab_index = shstrtab_index + 2;
+
+    section_headers.push(Elf64SectionHeader::new(
+        section_header_string_table.add(".shstrtab"),
+        SHT_STRTAB,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0,
+    ));
+    section_headers.push(Elf64SectionHeader::new(
+        section_header_string_table.add(".symtab"),
+        SHT_SYMTAB,
+        0,
+        0,
+        0,
+        0,
+        strtab_index as u32,
+        first_global_index as u32,
+        8,
+        size_of::<Elf64SymbolTableSection>() as u64,
+    ));
+    section_headers.push(Elf64SectionHeader::new(
+        section_header_string_table.add(".strtab"),
+        SHT_STRTAB,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0,
+    ));
+
+    let (section_header_string_table, section_name_offsets) = section_header_string_table.build();
+    for section_header in section_headers.iter_mut() {
+        section_header.set_name(section_name_offsets[section_header.name() as usize]);
+    }
+
+    let (string_table, symbol_name_offsets) = string_table.build();
+    let mut _symbol_table = Vec::<u8>::new();
+    for symbol in symbol_table.iter_mut() {
+        symbol.set_name(symbol_name_offsets[symbol.name() as usize]);
+        _symbol_table.extend(symbol.as_u8_slice());
+    }
+
+    // .shstrtab, .symtab and .strtab at the end of the file
+    for (i, data) in [section_header_string_table, _symbol_table, string_table]
+        .into_iter()
+        .enumerate()
+    {
+        let section_header = &mut section_headers[shstrtab_index + i];
+        bytes.resize(
+            align_up(bytes.len() as u64, section_header.align()) as usize,
+            0x0,
+        );
+        section_header.set_offset(bytes.len() as u64);
+        section_header.set_size(data.len() as u64);
+        bytes.extend(data);
+    }
+
+    bytes.resize(align_up(bytes.len() as u64, 8) as usize, 0x0);
+    let section_header_offset = bytes.len();
+    for section_header in section_headers.iter() {
+        bytes.extend(section_header.as_u8_slice());
+    }
+
+    let mut header = Elf64Header::template();
+    header.set_object_type(object_type);
+    header.set_entry(entry_address);
+    header.set_program_header_offset(size_of::<Elf64Header>() as u64);
+    header.set_program_header_size(size_of::<Elf64ProgramHeader>() as u16);
+    header.set_program_header_num(layout.program_headers.len() as u16);
+    header.set_section_header_offset(section_header_offset as u64);
+    header.set_section_header_num(section_headers.len() as u16);
+    header.set_section_header_str_index(shstrtab_index as u16);
+
+    let mut headers = header.as_u8_slice().to_vec();
+    for program_header in layout.program_headers.iter() {
+        headers.extend(program_header.as_u8_slice());
+    }
+    bytes[..headers.len()].copy_from_slice(&headers);
+
+    if let Some(build_id) = program.build_id {
+        let i = program
+            .section_nodes
+            .iter()
+            .position(|s| s.name == ".note.gnu.build-id")
+            .unwrap();
+        fill_build_id(&mut bytes, layout.section_offsets[i] as usize, build_id);
+    }
+
+    let mut file = File::create(output_filepath).expect("Failed to create file");
+    file.write_all(&bytes).expect("Failed to write file");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(Permissions::from_mode(0o755))
+            .expect("Failed to set permissions");
+    }
+
+    file
+}
+
+pub fn gen_exec(
+    input_filepath: &Path,
+    output_filepath: &Path,
+    entry: &str,
+    debug: bool,
+    features: u32,
+    endbr: bool,
+    build_id: Option<BuildId>,
+) -> File {
+    let tokens = parse_file(input_filepath);
+    let mut program = gen_program(&tokens, input_filepath, 64, endbr);
+    if program.origin.is_some() {
+        panic!("\"org\" is only supported in bin format");
+    }
+    allocate_common_symbols(&mut program);
+    merge_sections(&mut program);
+    if debug {
+        add_debug_sections(&mut program, input_filepath, 8);
+    }
+    add_eh_frame(&mut program, 64);
+    add_eh_frame_hdr(&mut program);
+    add_property_note(&mut program, 64, features);
+    if let Some(build_id) = build_id {
+        add_build_id_note(&mut program, 64, build_id);
+    }
+
+    let layout = layout_image(&program, BASE_ADDRESS, 0);
+    let mut bytes = vec![0x0; layout.size as usize];
+
+    // there is nothing to link with
+    let external_address = |name: &str| -> Option<i64> {
+        // undefined weak symbols are zero
+        match program.symbol_attributes(name).is_weak {
+            true => Some(0),
+            false => None,
+        }
+    };
+
+    for (i, offset) in layout.section_offsets.iter().enumerate() {
+        let data = link_section(&program, i, &layout.section_addresses, &external_address);
+        let start = *offset as usize;
+        bytes[start..start + data.len()].copy_from_slice(&data);
+    }
+    fill_eh_frame_hdr(&program, &layout, &mut bytes);
+
+    let entry_address = match program.find_label(entry) {
+        Some(label) => layout.section_addresses[label.section_index] + label.offset,
+        None => panic!("Entry symbol \"{}\" is not defined", entry),
+    };
+
+    write_image(
+        &program,
+        input_filepath,
+        output_filepath,
+        ET_EXEC,
+        entry_address,
+        &layout,
+        bytes,
+    )
+}
+
+#[test]
+fn test_stack_flags() {
+    use std::{env::temp_dir, fs};
+
+    let stack_flags = |asm: &str, name: &str| {
+        let input_filepath = temp_dir().join(format!("rasm_{}.asm", name));
+        let output_filepath = input_filepath.with_extension("");
+        fs::write(&input_filepath, asm).unwrap();
+        gen_exec(
+            &input_filepath,
+            &output_filepath,
+            "_start",
+            false,
+            0,
+            false,
+            None,
+        );
+        let bytes = fs::read(&output_filepath).unwrap();
+
+        let program_header_offset = u64::from_le_bytes(bytes[0x20..0x28].try_into().unwrap());
+        let program_header_num = u16::from_le_bytes(bytes[0x38..0x3a].try_into().unwrap());
+        (0..program_header_num as usize)
+            .map(|i| program_header_offset as usize + i * size_of::<Elf64ProgramHeader>())
+            .find(|h| u32::from_le_bytes(bytes[*h..*h + 4].try_into().unwrap()) == PT_GNU_STACK)
+            .map(|h| u32::from_le_bytes(bytes[h + 4..h + 8].try_into().unwrap()))
+            .unwrap()
+    };
+
+    let asm = "global _start\nsection .text\n_start:\nret";
+    assert_eq!(stack_flags(asm, "stack_flags"), PF_R | PF_W);
+    let asm = format!("section .note.GNU-stack exec\n{}", asm);
+    assert_eq!(stack_flags(&asm, "stack_flags_exec"), PF_R | PF_W | PF_X);
+}
+
+#[test]
+fn test_eh_frame_hdr() {
+    use crate::generator::read_sections;
+    use std::{env::temp_dir, fs};
+
+    // the second procedure comes first in memory
+    let asm = "
+        global _start
+        section .text.a
+        section .text.b
+        second:
+            cfi_startproc
+            ret
+            cfi_endproc
+        section .text.a
+        _start:
+            cfi_startproc
+            call second
+            ret
+            cfi_endproc
+    ";
+    let input_filepath = temp_dir().join("rasm_eh_frame_hdr.asm");
+    let output_filepath = input_filepath.with_extension("");
+    fs::write(&input_filepath, asm).unwrap();
+    gen_exec(
+        &input_filepath,
+        &output_filepath,
+        "_start",
+        false,
+        0,
+        false,
+        None,
+    );
+    let bytes = fs::read(&output_filepath).unwrap();
+    let sections = read_sections(&bytes);
+    let section = |name: &str| sections.iter().find(|s| s.name == name).unwrap();
+    let hdr = section(".eh_frame_hdr");
+    let word = |n: usize| i32::from_le_bytes(hdr.data[n * 4..n * 4 + 4].try_into().unwrap());
+
+    // found by the unwinder through PT_GNU_EH_FRAME
+    let program_header_offset = u64::from_le_bytes(bytes[0x20..0x28].try_into().unwrap());
+    let program_header_num = u16::from_le_bytes(bytes[0x38..0x3a].try_into().unwrap());
+    let address = (0..program_header_num as usize)
+        .map(|i| program_header_offset as usize + i * size_of::<Elf64ProgramHeader>())
+        .find(|h| u32::from_le_bytes(bytes[*h..*h + 4].try_into().unwrap()) == PT_GNU_EH_FRAME)
+        .map(|h| u64::from_le_bytes(bytes[h + 16..h + 24].try_into().unwrap()))
+        .unwrap();
+    assert_eq!(address, hdr.address);
+
+    assert_eq!(hdr.data[..4], [1, 0x1b, 0x03, 0x3b]);
+    let eh_frame = section(".eh_frame");
+    assert_eq!(address as i64 + 4 + word(1) as i64, eh_frame.address as i64);
+    assert_eq!(word(2), 2);
+    // sorted by the start of the procedure, each FDE starts with it
+    let starts: Vec<i64> = [3, 5].map(|n| address as i64 + word(n) as i64).to_vec();
+    assert_eq!(
+        starts,
+        [
+            section(".text.a").address as i64,
+            section(".text.b").address as i64
+        ]
+    );
+    for n in [4, 6] {
+        let fde = (address as i64 + word(n) as i64 - eh_frame.address as i64) as usize;
+        let pc_begin = i32::from_le_bytes(eh_frame.data[fde + 8..fde + 12].try_into().unwrap());
+        let start = eh_frame.address as i64 + fde as i64 + 8 + pc_begin as i64;
+        assert_eq!(start, starts[n / 2 - 2]);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::parse::parse_number;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    // offset in a section, what "$" and "$$" become
+    Position(usize, u64),
+    // "expr wrt ..kind", relocated by the kind
+    Wrt(Box<Expr>, Wrt),
+}
+
+// special relocations given by "wrt", mostly thread-local access models
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrt {
+    // call through the plt
+    Plt,
+    // offset from the thread pointer (local exec)
+    TpOff,
+    // got entry of the offset from the thread pointer (initial exec)
+    GotTpOff,
+    TlsIe,
+    // got entries for __tls_get_addr (general and local dynamic)
+    TlsGd,
+    TlsLd,
+    // offset in the module's tls block
+    DtpOff,
+    // rip-relative address of the got entry
+    GotPcRel,
+}
+
+impl Wrt {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word.to_lowercase().as_str() {
+            "..plt" => Some(Wrt::Plt),
+            "..tpoff" => Some(Wrt::TpOff),
+            "..gottpoff" => Some(Wrt::GotTpOff),
+            "..tlsie" => Some(Wrt::TlsIe),
+            "..tlsgd" => Some(Wrt::TlsGd),
+            "..tlsld" => Some(Wrt::TlsLd),
+            "..dtpoff" => Some(Wrt::DtpOff),
+            "..gotpcrel" => Some(Wrt::GotPcRel),
+            _ => None,
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        !matches!(self, Wrt::Plt | Wrt::GotPcRel)
+    }
+}
+
+// what a value is relative to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    // start of a section
+    Section(usize),
+    // symbol not defined in this file
+    Symbol(usize),
+}
+
+// result of evaluation, offset relative to the base if any
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value {
+    pub base: Option<Base>,
+    pub offset: i64,
+}
+
+impl Value {
+    pub fn constant(offset: i64) -> Self {
+        Self { base: None, offset }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(&'static str),
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '@' | '?')
+}
+
+fn tokenize(s: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if (c == '<' || c == '>') && chars.get(i + 1) == Some(&c) {
+            tokens.push(Token::Op(if c == '<' { "<<" } else { ">>" }));
+            i += 2;
+            continue;
+        }
+
+        if let Some(op) = ["|", "^", "&", "+", "-", "*", "/", "%", "~", "(", ")"]
+            .into_iter()
+            .find(|op| op.starts_with(c))
+        {
+            tokens.push(Token::Op(op));
+            i += 1;
+            continue;
+        }
+
+        if !is_symbol_char(c) {
+            return None;
+        }
+
+        let start = i;
+        while i < chars.len() && is_symbol_char(chars[i]) {
+            i += 1;
+        }
+        let word: String = chars[start..i].iter().collect();
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            tokens.push(Token::Number(parse_number(&word)?));
+        } else {
+            tokens.push(Token::Symbol(word));
+        }
+    }
+
+    Some(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    // binary operators from the lowest precedence
+    fn parse_binary(&mut self, level: usize) -> Option<Expr> {
+        const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+            &[("|", BinaryOp::Or)],
+            &[("^", BinaryOp::Xor)],
+            &[("&", BinaryOp::And)],
+            &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Mod),
+            ],
+        ];
+
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some((_, op)) = self
+            .peek_op()
+            .and_then(|p| LEVELS[level].iter().find(|(o, _)| *o == p))
+        {
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Some(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Option<Expr> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => Some(Expr::Number(n)),
+            Token::Symbol(s) => Some(Expr::Symbol(s)),
+            Token::Op("-") => Some(Expr::Neg(Box::new(self.parse_unary()?))),
+            Token::Op("+") => self.parse_unary(),
+            Token::Op("~") => Some(Expr::Not(Box::new(self.parse_unary()?))),
+            Token::Op("(") => {
+                let expr = self.parse_binary(0)?;
+                if self.peek_op() != Some(")") {
+                    return None;
+                }
+                self.pos += 1;
+                Some(expr)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+
+        let mut expr = parser.parse_binary(0)?;
+        if let [Token::Symbol(word), Token::Symbol(kind)] = &parser.tokens[parser.pos..] {
+            if word.eq_ignore_ascii_case("wrt") {
+                expr = Expr::Wrt(Box::new(expr), Wrt::parse(kind)?);
+                parser.pos += 2;
+            }
+        }
+        if parser.pos != parser.tokens.len() {
+            return None;
+        }
+
+        Some(expr)
+    }
+
+    // prefix local labels (".foo") with the last non-local label
+    pub fn expand_local_labels(&mut self, prefix: &str) {
+        match self {
+            Expr::Number(_) | Expr::Position(_, _) => (),
+            Expr::Symbol(s) => {
+                if s.starts_with('.') {
+                    *s = format!("{}{}", prefix, s);
+                }
+            }
+            Expr::Neg(e) | Expr::Not(e) | Expr::Wrt(e, _) => e.expand_local_labels(prefix),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.expand_local_labels(prefix);
+                rhs.expand_local_labels(prefix);
+            }
+        }
+    }
+
+    // replace "$" with the given position and "$$" with the start of its section
+    pub fn expand_positions(&mut self, section: usize, offset: u64) {
+        match self {
+            Expr::Number(_) | Expr::Position(_, _) => (),
+            Expr::Symbol(s) => match s.as_str() {
+                "$" => *self = Expr::Position(section, offset),
+                "$$" => *self = Expr::Position(section, 0),
+                _ => (),
+            },
+            Expr::Neg(e) | Expr::Not(e) | Expr::Wrt(e, _) => e.expand_positions(section, offset),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.expand_positions(section, offset);
+                rhs.expand_positions(section, offset);
+            }
+        }
+    }
+
+    // move positions in a section whose contents were rearranged
+    pub fn remap_positions(&mut self, section: usize, remap: &dyn Fn(u64) -> u64) {
+        match self {
+            Expr::Position(s, offset) if *s == section => *offset = remap(*offset),
+            Expr::Number(_) | Expr::Symbol(_) | Expr::Position(_, _) => (),
+            Expr::Neg(e) | Expr::Not(e) | Expr::Wrt(e, _) => e.remap_positions(section, remap),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.remap_positions(section, remap);
+                rhs.remap_positions(section, remap);
+            }
+        }
+    }
+
+    // names of the symbols it refers to
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) | Expr::Position(_, _) => Vec::new(),
+            Expr::Symbol(s) => vec![s.as_str()],
+            Expr::Neg(e) | Expr::Not(e) | Expr::Wrt(e, _) => e.symbols(),
+            Expr::Binary(_, lhs, rhs) => {
+                let mut names = lhs.symbols();
+                names.extend(rhs.symbols());
+                names
+            }
+        }
+    }
+
+    pub fn eval(&self, resolve: &dyn Fn(&str) -> Option<Value>) -> Option<Value> {
+        match self {
+            Expr::Number(n) => Some(Value::constant(*n)),
+            Expr::Symbol(s) => resolve(s),
+            Expr::Position(section, offset) => Some(Value {
+                base: Some(Base::Section(*section)),
+                offset: *offset as i64,
+            }),
+            Expr::Wrt(e, _) => e.eval(resolve),
+            Expr::Neg(e) => {
+                let v = e.eval(resolve)?;
+                if v.base.is_some() {
+                    return None;
+                }
+                Some(Value::constant(v.offset.wrapping_neg()))
+            }
+            Expr::Not(e) => {
+                let v = e.eval(resolve)?;
+                if v.base.is_some() {
+                    return None;
+                }
+                Some(Value::constant(!v.offset))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(resolve)?;
+                let rhs = rhs.eval(resolve)?;
+
+                match (op, lhs.base, rhs.base) {
+                    // relative values can only be moved or subtracted
+                    (BinaryOp::Add, Some(_), None) => Some(Value {
+                        base: lhs.base,
+                        offset: lhs.offset.wrapping_add(rhs.offset),
+                    }),
+                    (BinaryOp::Add, None, Some(_)) => Some(Value {
+                        base: rhs.base,
+                        offset: lhs.offset.wrapping_add(rhs.offset),
+                    }),
+                    (BinaryOp::Sub, Some(_), None) => Some(Value {
+                        base: lhs.base,
+                        offset: lhs.offset.wrapping_sub(rhs.offset),
+                    }),
+                    (BinaryOp::Sub, Some(Base::Section(l)), Some(Base::Section(r))) if l == r => {
+                        Some(Value::constant(lhs.offset.wrapping_sub(rhs.offset)))
+                    }
+                    (_, None, None) => {
+                        Some(Value::constant(eval_binary(*op, lhs.offset, rhs.offset)?))
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+fn eval_binary(op: BinaryOp, lhs: i64, rhs: i64) -> Option<i64> {
+    Some(match op {
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => ((lhs as u64).wrapping_shr(rhs as u32)) as i64,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div => lhs.checked_div(rhs)?,
+        BinaryOp::Mod => lhs.checked_rem(rhs)?,
+    })
+}
+
+#[test]
+fn test_eval() {
+    let resolve = |s: &str| match s {
+        "tbl" => Some(Value {
+            base: Some(Base::Section(1)),
+            offset: 8,
+        }),
+        "tbl.end" => Some(Value {
+            base: Some(Base::Section(1)),
+            offset: 24,
+        }),
+        _ => None,
+    };
+
+    let eval = |s: &str| Expr::parse(s).unwrap().eval(&resolve);
+
+    assert_eq!(eval("tbl.end-tbl"), Some(Value::constant(16)));
+    assert_eq!(eval("(1 + 2) * 3 - -1"), Some(Value::constant(10)));
+    assert_eq!(eval("1 << 4 | 0x0f"), Some(Value::constant(31)));
+    assert_eq!(
+        eval("tbl + 4"),
+        Some(Value {
+            base: Some(Base::Section(1)),
+            offset: 12
+        })
+    );
+    assert_eq!(eval("tbl * 2"), None);
+    assert_eq!(eval("undefined"), None);
+    assert!(Expr::parse("1 +").is_none());
+    assert_eq!(eval("tbl + 4 wrt ..gottpoff"), eval("tbl + 4"));
+    assert!(Expr::parse("tbl wrt ..unknown").is_none());
+}
@@ -1,8 +1,26 @@
-use std::{fmt::format, fs::File, io::*, mem::size_of, path::Path, string};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::*,
+    mem::size_of,
+    path::Path,
+};
 
-use crate::{elf::*, node::SectionNode, parse::*};
+use crate::{
+    dwarf::{add_debug_sections, add_eh_frame},
+    elf::*,
+    encode::{constant, encode, nop_padding, patch, FixupKind},
+    exec::reserve,
+    expr::{Base, Expr, Value, Wrt},
+    node::{FrameNode, LabelNode, ProgramNode, SectionNode, SymbolAttributes},
+    note::{add_build_id_note, add_property_note, fill_build_id, BuildId},
+    operand::MemoryOperand,
+    parse::*,
+    strtab::StringTable,
+};
 
-pub fn gen_elf(input_filepath: &Path, output_filepath: &Path) -> File {
+// read and check a source file
+pub fn parse_file(input_filepath: &Path) -> Vec<LineToken> {
     let mut text = String::new();
     let mut input_file = File::open(input_filepath).expect("File not found");
     input_file
@@ -12,19 +30,18 @@ pub fn gen_elf(input_filepath: &Path, output_filepath: &Path) -> File {
     let lines: Vec<&str> = text.split("\n").collect();
     let mut tokens = Vec::new();
 
-    for (i, line) in lines.iter().enumerate() {
-        let token = parse(*line);
-        println!("line {}: \"{}\" => {:?}", i + 1, line, token);
-        tokens.push(token);
+    for line in lines.iter() {
+        tokens.push(parse(line));
     }
 
     match check_tokens(&tokens) {
         CheckResult::Ok => (),
         CheckResult::Error { at, error_type } => {
+            // where the line came from if it's generated
+            let (files, locations) = source_locations(&tokens);
             println!(
-                "{}, line{}: \"{}\" is {:?}",
-                input_filepath.to_str().unwrap(),
-                at + 1,
+                "{}: \"{}\" is {:?}",
+                location_name(input_filepath.to_str().unwrap(), &files, locations[at]),
                 lines[at],
                 error_type
             );
@@ -32,416 +49,1974 @@ pub fn gen_elf(input_filepath: &Path, output_filepath: &Path) -> File {
         }
     }
 
-    let mut section_nodes = Vec::new();
-    let mut text_section_node = SectionNode::new(".text".to_string());
-    text_section_node.global_labels.push("_start".to_string());
-    let mut current_section_node: Option<SectionNode> = None;
-    let mut current_label_with_instructions: Option<(String, Vec<Instruction>)> = None;
+    tokens
+}
+
+// "file, lineN" of a source location, the way errors point at lines
+pub fn location_name(input_name: &str, files: &[String], (file, line): (usize, usize)) -> String {
+    match file {
+        0 => format!("{}, line{}", input_name, line),
+        _ => format!("{}, line{}", files[file - 1], line),
+    }
+}
+
+// (file, line) of each line, rewritten by "%line" and ".loc", and the files other than the input
+pub fn source_locations(tokens: &[LineToken]) -> (Vec<String>, Vec<(usize, usize)>) {
+    let mut files: Vec<String> = Vec::new();
+    // numbers given by ".file"
+    let mut file_numbers = HashMap::new();
+    // (file, line, increment) of the next line given by "%line"
+    let mut mapping: Option<(usize, usize, usize)> = None;
+    // given by ".loc" until another one
+    let mut loc: Option<(usize, usize)> = None;
+    let mut locations = Vec::new();
+
+    let file_index =
+        |files: &mut Vec<String>, name: &str| match files.iter().position(|f| f == name) {
+            Some(i) => i + 1,
+            None => {
+                files.push(name.to_string());
+                files.len()
+            }
+        };
+
+    for (i, token) in tokens.iter().enumerate() {
+        locations.push(match (loc, mapping) {
+            (Some(location), _) => location,
+            (None, Some((file, line, _))) => (file, line),
+            (None, None) => (0, i + 1),
+        });
+        if let Some((_, line, increment)) = &mut mapping {
+            *line += *increment;
+        }
 
-    for (_, token) in tokens.iter().enumerate() {
         match token {
-            LineToken::Invalid => unreachable!(), // have to paniced at token checker
-            LineToken::Empty => continue,
-            LineToken::Comment => continue,
-            LineToken::Instruction(ins) => {
-                if let Some((_, ref mut instructions)) = current_label_with_instructions {
-                    instructions.push(ins.clone());
-                } else {
-                    if let Some(ref mut section_node) = current_section_node {
-                        section_node.default_instructions.push(ins.clone());
-                    } else {
-                        text_section_node.default_instructions.push(ins.clone());
-                    }
-                }
+            LineToken::Directive(Directive::Line(line, increment, name)) => {
+                let file = match name {
+                    Some(name) => file_index(&mut files, name),
+                    None => mapping.map(|(file, _, _)| file).unwrap_or(0),
+                };
+                mapping = Some((file, *line, *increment));
+                loc = None;
             }
-            LineToken::Directive(dir) => match dir {
-                Directive::Global(labels) => {
-                    if let Some(ref mut section_node) = current_section_node {
-                        push_global_labels(labels, section_node);
-                    } else {
-                        push_global_labels(labels, &mut text_section_node);
-                    }
-                }
-                Directive::Section(section_name) => {
-                    puah_current_label_with_instructions(
-                        &mut current_label_with_instructions,
-                        &mut current_section_node,
-                        &mut text_section_node,
-                    );
+            LineToken::Directive(Directive::File(number, name)) => {
+                file_numbers.insert(*number, file_index(&mut files, name));
+            }
+            LineToken::Directive(Directive::Loc(number, line)) => {
+                loc = match file_numbers.get(number) {
+                    Some(file) => Some((*file, *line)),
+                    None => panic!(".loc uses file {} not given by .file", number),
+                };
+            }
+            _ => (),
+        }
+    }
 
-                    if !section_name.eq(".text") {
-                        current_section_node = Some(SectionNode::new(section_name.clone()));
-                    } else {
-                        current_section_node = None;
-                    }
-                }
+    (files, locations)
+}
+
+// label, its symbol and the real section header index, st_shndx may be SHN_XINDEX
+pub type LabelSymbol<'a> = (&'a LabelNode, Elf64SymbolTableSection, usize);
+
+// symbols of labels, locals and globals separately, named by string table ids,
+// "place" gives the section header index and value of a label
+pub fn label_symbols<'a>(
+    program: &'a ProgramNode,
+    string_table: &mut StringTable,
+    place: &dyn Fn(&LabelNode) -> (usize, u64),
+) -> (Vec<LabelSymbol<'a>>, Vec<LabelSymbol<'a>>) {
+    let mut local_symbols = Vec::new();
+    let mut global_symbols = Vec::new();
+
+    for label in program.labels.iter() {
+        let attributes = program.symbol_attributes(&label.name);
+        let size = symbol_size(program, &label.name);
+
+        // everything in a thread-local section is a thread-local symbol
+        let s_type = match program.section_nodes[label.section_index].attributes.flags & SHF_TLS {
+            0 => attributes.st_type(),
+            _ => STT_TLS,
+        };
+
+        let (index, value) = place(label);
+        let symbol = Elf64SymbolTableSection::new(
+            string_table.add(&label.name),
+            st_info(attributes.st_bind(), s_type),
+            attributes.st_other(),
+            symbol_section_index(index),
+            value,
+            size,
+        );
+
+        if attributes.st_bind() == STB_LOCAL {
+            local_symbols.push((label, symbol, index));
+        } else {
+            global_symbols.push((label, symbol, index));
+        }
+    }
+
+    (local_symbols, global_symbols)
+}
+
+// given by "global name:type size"
+pub fn symbol_size(program: &ProgramNode, name: &str) -> u64 {
+    let resolve = |name: &str| program.resolve(name);
+
+    match &program.symbol_attributes(name).size {
+        Some(size) => match size.eval(&resolve) {
+            Some(value) if value.base.is_none() => value.offset as u64,
+            _ => panic!("Invalid size of symbol \"{}\"", name),
+        },
+        None => 0,
+    }
+}
+
+// size and alignment of a common symbol
+pub fn common_symbol_layout(name: &str, size: &Expr, align: &Option<Expr>) -> (u64, u64) {
+    let size = match constant(size) {
+        Some(size) if size >= 0 => size as u64,
+        _ => panic!("Invalid size of common symbol \"{}\"", name),
+    };
+
+    // same as gas, the largest power of two up to the size (max 16)
+    let align = match align.as_ref().map(constant) {
+        Some(Some(align)) if align > 0 && (align as u64).is_power_of_two() => align as u64,
+        Some(_) => panic!("Invalid alignment of common symbol \"{}\"", name),
+        None => 1 << size.clamp(1, 16).ilog2(),
+    };
+
+    (size, align)
+}
+
+// "db" of the bytes repeated "times"
+pub fn data_bytes(bytes: &[u8], times: u64, bits: u8) -> Instruction {
+    Instruction {
+        mnemonic: Mnemonic::Db,
+        operands: bytes
+            .iter()
+            .map(|b| Operand::Immediate(Expr::Number(*b as i64)))
+            .collect(),
+        times,
+        repeat: None,
+        bits,
+        line: 0,
+        file: 0,
+    }
+}
+
+// (start, end) of each entry of a mergeable section
+fn merge_entries(
+    name: &str,
+    data: &[u8],
+    entry_size: usize,
+    is_strings: bool,
+) -> Vec<(usize, usize)> {
+    if !data.len().is_multiple_of(entry_size) {
+        panic!(
+            "Size of mergeable section \"{}\" is not a multiple of {}",
+            name, entry_size
+        );
+    }
+
+    let mut entries = Vec::new();
+    let mut start = 0;
+    for end in (entry_size..=data.len()).step_by(entry_size) {
+        // strings end with a null character
+        if !is_strings || data[end - entry_size..end].iter().all(|b| *b == 0) {
+            entries.push((start, end));
+            start = end;
+        }
+    }
+
+    if start != data.len() {
+        panic!("String in mergeable section \"{}\" is not terminated", name);
+    }
+
+    entries
+}
+
+// drop duplicate entries of mergeable sections, the linker merges them across objects
+pub fn merge_sections(program: &mut ProgramNode) {
+    for index in 0..program.section_nodes.len() {
+        let section = &program.section_nodes[index];
+        let attributes = &section.attributes;
+        if attributes.flags & SHF_MERGE == 0 || section.is_nobits() {
+            continue;
+        }
+        if attributes.entry_size == 0 {
+            panic!("Mergeable section \"{}\" needs an entry size", section.name);
+        }
+
+        let (data, fixups) = program.encode_section(index);
+        if !fixups.is_empty() {
+            panic!(
+                "Mergeable section \"{}\" can't have relocations",
+                section.name
+            );
+        }
+
+        let entries = merge_entries(
+            &section.name,
+            &data,
+            attributes.entry_size as usize,
+            attributes.flags & SHF_STRINGS != 0,
+        );
+        let mut merged: Vec<u8> = Vec::new();
+        let mut merged_offsets = HashMap::new();
+        let mut entry_offsets = Vec::new();
+        for (start, end) in entries.iter() {
+            let entry = &data[*start..*end];
+            let offset = *merged_offsets.entry(entry).or_insert_with(|| {
+                merged.extend(entry);
+                merged.len() - entry.len()
+            });
+            entry_offsets.push(offset);
+        }
+
+        if merged.len() == data.len() {
+            continue;
+        }
+
+        // offsets in the section move along with their entries
+        let merged_size = merged.len() as u64;
+        let remap = |offset: u64| -> u64 {
+            match entries
+                .iter()
+                .position(|(start, end)| (*start..*end).contains(&(offset as usize)))
+            {
+                Some(i) => (entry_offsets[i] + offset as usize - entries[i].0) as u64,
+                None => merged_size,
+            }
+        };
+
+        for label in program.labels.iter_mut() {
+            if label.section_index == index {
+                label.offset = remap(label.offset);
+            }
+        }
+        for section in program.section_nodes.iter_mut() {
+            for ins in section.instructions.iter_mut() {
+                ins.for_each_expr(&mut |expr| expr.remap_positions(index, &remap));
+            }
+        }
+
+        let section = &mut program.section_nodes[index];
+        let bits = section.instructions[0].bits;
+        section.instructions.clear();
+        section.size = 0;
+        section.push_instruction(data_bytes(&merged, 1, bits));
+    }
+}
+
+pub fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+// encode a section with every value resolved to an address
+pub fn link_section(
+    program: &ProgramNode,
+    index: usize,
+    section_addresses: &[u64],
+    // symbols not defined in the program are up to the output format, None if undefined
+    external_address: &dyn Fn(&str) -> Option<i64>,
+) -> Vec<u8> {
+    let section_node = &program.section_nodes[index];
+    let (mut data, fixups) = program.encode_section(index);
+
+    for fixup in fixups {
+        let location = location_name(&program.input_name, &program.files, fixup.location);
+        let resolve = |name: &str| match program.resolve(name) {
+            Some(Value {
+                base: Some(Base::Symbol(_)),
+                ..
+            })
+            | None => match external_address(name) {
+                Some(address) => Some(Value::constant(address)),
+                None => panic!("{}: Undefined symbol \"{}\"", location, name),
             },
-            LineToken::Label(label) => {
-                puah_current_label_with_instructions(
-                    &mut current_label_with_instructions,
-                    &mut current_section_node,
-                    &mut text_section_node,
-                );
+            value => value,
+        };
 
-                current_label_with_instructions = Some((label.clone(), Vec::new()));
+        // calls through the plt are direct here
+        if let Expr::Wrt(_, wrt) = fixup.expr {
+            if wrt != Wrt::Plt {
+                panic!(
+                    "{}: Reference ({:?}) in section \"{}\" needs a linker",
+                    location, wrt, section_node.name
+                );
             }
         }
+
+        let value = match fixup.expr.eval(&resolve) {
+            Some(value) => value,
+            None => panic!(
+                "{}: Invalid expression in section \"{}\"",
+                location, section_node.name
+            ),
+        };
+
+        let address = match value.base {
+            None => value.offset,
+            Some(Base::Section(s)) => section_addresses[s] as i64 + value.offset,
+            Some(Base::Symbol(_)) => unreachable!(),
+        };
+
+        let address = match fixup.kind {
+            FixupKind::Relative => address - (section_addresses[index] + fixup.end) as i64,
+            _ => address,
+        };
+
+        if !patch(&mut data, fixup.offset, fixup.size, fixup.kind, address) {
+            panic!(
+                "{}: Value out of range in section \"{}\"",
+                location, section_node.name
+            );
+        }
     }
 
-    puah_current_label_with_instructions(
-        &mut current_label_with_instructions,
-        &mut current_section_node,
-        &mut text_section_node,
-    );
+    data
+}
+
+fn relocation_type(kind: FixupKind, size: u8, bits: u8) -> Option<u32> {
+    if bits == 32 {
+        return match (kind, size) {
+            (FixupKind::Absolute | FixupKind::Signed, 4) => Some(R_386_32),
+            (FixupKind::Absolute | FixupKind::Signed, 2) => Some(R_386_16),
+            (FixupKind::Absolute | FixupKind::Signed, 1) => Some(R_386_8),
+            (FixupKind::Relative, 4) => Some(R_386_PC32),
+            (FixupKind::Relative, 2) => Some(R_386_PC16),
+            (FixupKind::Relative, 1) => Some(R_386_PC8),
+            _ => None,
+        };
+    }
 
-    if current_section_node.is_some() {
-        section_nodes.push(current_section_node.unwrap());
+    match (kind, size) {
+        (FixupKind::Absolute, 8) => Some(R_X86_64_64),
+        (FixupKind::Absolute, 4) => Some(R_X86_64_32),
+        (FixupKind::Signed, 4) => Some(R_X86_64_32S),
+        (FixupKind::Absolute | FixupKind::Signed, 2) => Some(R_X86_64_16),
+        (FixupKind::Absolute | FixupKind::Signed, 1) => Some(R_X86_64_8),
+        (FixupKind::Relative, 4) => Some(R_X86_64_PC32),
+        (FixupKind::Relative, 2) => Some(R_X86_64_PC16),
+        (FixupKind::Relative, 1) => Some(R_X86_64_PC8),
+        _ => None,
     }
+}
 
-    section_nodes.push(text_section_node);
-    println!("{:#?}", section_nodes);
+// relocation of a "wrt ..x" reference, None if there is none for the fixup
+fn wrt_relocation_type(wrt: Wrt, kind: FixupKind, size: u8, bits: u8) -> Option<u32> {
+    if size != 4 {
+        return None;
+    }
+
+    if bits == 32 {
+        return match (wrt, kind) {
+            (Wrt::Plt, FixupKind::Relative) => Some(R_386_PLT32),
+            (Wrt::TpOff, FixupKind::Absolute) => Some(R_386_TLS_LE),
+            (Wrt::TlsIe, FixupKind::Absolute) => Some(R_386_TLS_IE),
+            (Wrt::TlsGd, FixupKind::Absolute) => Some(R_386_TLS_GD),
+            (Wrt::TlsLd, FixupKind::Absolute) => Some(R_386_TLS_LDM),
+            (Wrt::DtpOff, FixupKind::Absolute) => Some(R_386_TLS_LDO_32),
+            _ => None,
+        };
+    }
+
+    match (wrt, kind) {
+        (Wrt::Plt, FixupKind::Relative) => Some(R_X86_64_PLT32),
+        (Wrt::TpOff, FixupKind::Absolute | FixupKind::Signed) => Some(R_X86_64_TPOFF32),
+        (Wrt::DtpOff, FixupKind::Absolute | FixupKind::Signed) => Some(R_X86_64_DTPOFF32),
+        (Wrt::GotTpOff | Wrt::TlsIe, FixupKind::Relative) => Some(R_X86_64_GOTTPOFF),
+        (Wrt::TlsGd, FixupKind::Relative) => Some(R_X86_64_TLSGD),
+        (Wrt::TlsLd, FixupKind::Relative) => Some(R_X86_64_TLSLD),
+        (Wrt::GotPcRel, FixupKind::Relative) => Some(R_X86_64_GOTPCREL),
+        _ => None,
+    }
+}
+
+// relocatable object, ELF64 for 64 bits and ELF32 for 32 bits,
+// "features" are the CET features marked in .note.gnu.property
+pub fn gen_elf(
+    input_filepath: &Path,
+    output_filepath: &Path,
+    bits: u8,
+    debug: bool,
+    features: u32,
+    endbr: bool,
+    build_id: Option<BuildId>,
+) -> File {
+    let tokens = parse_file(input_filepath);
+    let mut program = gen_program(&tokens, input_filepath, bits, endbr);
+    if program.origin.is_some() {
+        panic!("\"org\" is only supported in bin format");
+    }
+    merge_sections(&mut program);
+    if debug {
+        add_debug_sections(&mut program, input_filepath, bits / 8);
+    }
+    add_eh_frame(&mut program, bits);
+    add_property_note(&mut program, bits, features);
+    if let Some(build_id) = build_id {
+        add_build_id_note(&mut program, bits, build_id);
+    }
+
+    // without this note linkers assume an executable stack
+    if !program
+        .section_nodes
+        .iter()
+        .any(|s| s.name == ".note.GNU-stack")
+    {
+        program
+            .section_nodes
+            .push(SectionNode::new(".note.GNU-stack".to_string()));
+    }
 
     let mut bytes: Vec<u8> = Vec::new();
 
-    let mut header = Elf64Header::template();
+    // COMDAT groups by signature, their headers come before the members
+    let mut groups: Vec<String> = Vec::new();
+    for section_node in program.section_nodes.iter() {
+        if let Some(group) = &section_node.attributes.group {
+            if !groups.contains(group) {
+                groups.push(group.clone());
+            }
+        }
+    }
+    let first_section_index = 1 + groups.len();
 
+    // names are string table ids until the table is built
     let mut symbol_table = vec![Elf64SymbolTableSection::default()];
-    let mut string_table = vec![0x0];
+    let mut string_table = StringTable::new();
 
     // file section
-    symbol_table.push(Elf64SymbolTableSection::new(1, 4, 0, 65521, 0, 0));
-    string_table.extend(format!("{}\0", input_filepath.to_str().unwrap()).as_bytes());
+    symbol_table.push(Elf64SymbolTableSection::new(
+        string_table.add(input_filepath.to_str().unwrap()),
+        st_info(STB_LOCAL, STT_FILE),
+        0,
+        SHN_ABS,
+        0,
+        0,
+    ));
 
-    let mut section_headers = vec![Elf64SectionHeader::default()];
-    let mut section_header_string_table = vec![0x0];
-    let mut data_bytes = Vec::new();
+    // (symbol, section header index) of the symbols in sections
+    let mut symbol_sections = Vec::new();
+    // symbol indexes of labels by name
+    let mut label_indexes: HashMap<&str, u32> = HashMap::new();
+
+    // section symbols, index 2 and after
+    for i in 0..program.section_nodes.len() {
+        symbol_sections.push((symbol_table.len(), first_section_index + i));
+        symbol_table.push(Elf64SymbolTableSection::new(
+            0,
+            st_info(STB_LOCAL, STT_SECTION),
+            0,
+            symbol_section_index(first_section_index + i),
+            0,
+            0,
+        ));
+    }
 
-    for section_node in section_nodes.iter() {
-        let mut data = Vec::new();
+    let (local_symbols, global_symbols) = label_symbols(&program, &mut string_table, &|label| {
+        (first_section_index + label.section_index, label.offset)
+    });
+    for (label, symbol, section) in local_symbols {
+        label_indexes.insert(&label.name, symbol_table.len() as u32);
+        symbol_sections.push((symbol_table.len(), section));
+        symbol_table.push(symbol);
+    }
 
-        for ins in section_node.default_instructions.iter() {
-            data.extend(ins.mnemonic.get_opcode());
+    // signatures that name no symbol get a local one in the first member
+    let mut signature_indexes = Vec::new();
+    for group in groups.iter() {
+        if program.resolve(group).is_some() {
+            signature_indexes.push(None);
+            continue;
         }
 
+        let section_index = program
+            .section_nodes
+            .iter()
+            .position(|s| s.attributes.group.as_ref() == Some(group))
+            .unwrap();
+        signature_indexes.push(Some(symbol_table.len() as u32));
+        symbol_sections.push((symbol_table.len(), first_section_index + section_index));
         symbol_table.push(Elf64SymbolTableSection::new(
+            string_table.add(group),
+            st_info(STB_LOCAL, STT_NOTYPE),
             0,
-            3,
-            0,
-            (symbol_table.len() - 1) as u16,
+            symbol_section_index(first_section_index + section_index),
             0,
             0,
         ));
+    }
 
-        for (label, instructions) in section_node.labeled_instructions.iter() {
-            for ins in instructions {
-                data.extend(ins.mnemonic.get_opcode());
-            }
+    let first_global_index = symbol_table.len();
+    for (label, symbol, section) in global_symbols {
+        label_indexes.insert(&label.name, symbol_table.len() as u32);
+        symbol_sections.push((symbol_table.len(), section));
+        symbol_table.push(symbol);
+    }
 
-            symbol_table.push(Elf64SymbolTableSection::new(
-                string_table.len() as u32,
-                2,
-                0,
-                (symbol_table.len() - 1) as u16,
+    // symbols referenced but not defined here
+    let external_symbols = program.external_symbols();
+    let mut external_indexes = Vec::new();
+    for name in external_symbols.iter() {
+        let attributes = program.symbol_attributes(name);
+        external_indexes.push(symbol_table.len() as u32);
+
+        let symbol = match program.common_symbols.iter().find(|(n, _, _)| n == name) {
+            Some((_, size, align)) => {
+                let (size, align) = common_symbol_layout(name, size, align);
+                Elf64SymbolTableSection::new(
+                    string_table.add(name),
+                    st_info(STB_GLOBAL, STT_OBJECT),
+                    attributes.st_other(),
+                    SHN_COMMON,
+                    align,
+                    size,
+                )
+            }
+            None => Elf64SymbolTableSection::new(
+                string_table.add(name),
+                st_info(
+                    if attributes.is_weak {
+                        STB_WEAK
+                    } else {
+                        STB_GLOBAL
+                    },
+                    attributes.st_type(),
+                ),
+                attributes.st_other(),
+                SHN_UNDEF,
                 0,
                 0,
-            ));
-            string_table.extend(format!("{}\0", label).as_bytes());
+            ),
+        };
+        symbol_table.push(symbol);
+    }
+
+    let label_index = |name: &str| label_indexes.get(name).copied();
+    for (group, index) in groups.iter().zip(signature_indexes.iter_mut()) {
+        if index.is_none() {
+            *index = match label_index(group) {
+                Some(index) => Some(index),
+                None => external_symbols
+                    .iter()
+                    .position(|n| n == group)
+                    .map(|i| external_indexes[i]),
+            };
         }
+    }
+
+    // section indexes of symbols with SHN_XINDEX
+    let mut extended_indexes = vec![0; symbol_table.len()];
+    for (symbol, section) in symbol_sections {
+        if symbol_table[symbol].index() == SHN_XINDEX {
+            extended_indexes[symbol] = section;
+        }
+    }
+    let has_extended_indexes = extended_indexes.iter().any(|index| *index != 0);
+
+    // linkers handle debug info and unwind tables of discarded groups themselves
+    let is_outside_group = |from: usize, to: usize| {
+        let from = &program.section_nodes[from];
+        let group = &program.section_nodes[to].attributes.group;
+        group.is_some()
+            && *group != from.attributes.group
+            && from.attributes.flags & SHF_ALLOC != 0
+            && from.name != ".eh_frame"
+    };
+
+    // labels of each mergeable section by offset, linkers can't tell which string
+    // a section symbol refers to, so relocations go through these like in gas
+    let mut merge_labels: HashMap<usize, Vec<&LabelNode>> = HashMap::new();
+    for label in program.labels.iter() {
+        if program.section_nodes[label.section_index].attributes.flags & SHF_MERGE != 0 {
+            merge_labels
+                .entry(label.section_index)
+                .or_default()
+                .push(label);
+        }
+    }
+    for labels in merge_labels.values_mut() {
+        labels.sort_by_key(|l| l.offset);
+    }
+
+    // section data and relocations
+    let resolve = |name: &str| program.resolve(name);
+    let mut section_data = Vec::new();
+    let mut relocations = Vec::new();
+
+    for (i, section_node) in program.section_nodes.iter().enumerate() {
+        let (mut data, fixups) = program.encode_section(i);
+        let mut relas = Vec::new();
+        let mut relocation_num = 0;
+
+        for fixup in fixups {
+            let location = location_name(&program.input_name, &program.files, fixup.location);
+            let value = match fixup.expr.eval(&resolve) {
+                Some(value) => value,
+                None => match fixup
+                    .expr
+                    .symbols()
+                    .into_iter()
+                    .find(|n| resolve(n).is_none())
+                {
+                    Some(name) => panic!("{}: Undefined symbol \"{}\"", location, name),
+                    None => panic!(
+                        "{}: Invalid expression in section \"{}\"",
+                        location, section_node.name
+                    ),
+                },
+            };
+            // distance from the field to the end of the instruction
+            let tail = (fixup.end - fixup.offset) as i64;
 
-        let data_len = data.len();
+            let wrt = match fixup.expr {
+                Expr::Wrt(_, wrt) => Some(wrt),
+                _ => None,
+            };
+            let tls = wrt.is_some_and(|wrt| wrt.is_tls());
+
+            let resolved = match (fixup.kind, value.base) {
+                // thread-local references always go through the symbol
+                _ if tls => None,
+                (FixupKind::Relative, Some(Base::Section(s))) if s == i => {
+                    Some(value.offset - fixup.end as i64)
+                }
+                (FixupKind::Absolute | FixupKind::Signed, None) => Some(value.offset),
+                _ => None,
+            };
+
+            if let Some(resolved) = resolved {
+                if !patch(&mut data, fixup.offset, fixup.size, fixup.kind, resolved) {
+                    panic!(
+                        "{}: Value out of range in section \"{}\"",
+                        location, section_node.name
+                    );
+                }
+                continue;
+            }
+
+            let (symbol, offset) = match value.base {
+                Some(Base::Section(s))
+                    if tls && program.section_nodes[s].attributes.flags & SHF_TLS != 0 =>
+                {
+                    match program
+                        .labels
+                        .iter()
+                        .filter(|l| l.section_index == s && l.offset as i64 <= value.offset)
+                        .max_by_key(|l| l.offset)
+                    {
+                        Some(label) => (
+                            label_index(&label.name).unwrap(),
+                            value.offset - label.offset as i64,
+                        ),
+                        None => panic!(
+                            "Thread-local reference in section \"{}\" has no symbol",
+                            section_node.name
+                        ),
+                    }
+                }
+                // defined as thread-local elsewhere
+                Some(Base::Symbol(s)) if tls => {
+                    let symbol = &mut symbol_table[external_indexes[s] as usize];
+                    symbol.set_info(st_info(symbol.info() >> 4, STT_TLS));
+                    (external_indexes[s], value.offset)
+                }
+                _ if tls => panic!(
+                    "Thread-local reference in section \"{}\" is not to a thread-local symbol",
+                    section_node.name
+                ),
+                None => (0, value.offset),
+                // the group may be discarded, so this goes through a global symbol in it
+                Some(Base::Section(s)) if is_outside_group(i, s) => {
+                    match program
+                        .labels
+                        .iter()
+                        .filter(|l| l.section_index == s && l.offset as i64 <= value.offset)
+                        .filter(|l| program.symbol_attributes(&l.name).st_bind() != STB_LOCAL)
+                        .max_by_key(|l| l.offset)
+                    {
+                        Some(label) => (
+                            label_index(&label.name).unwrap(),
+                            value.offset - label.offset as i64,
+                        ),
+                        None => panic!(
+                            "Section \"{}\" refers to COMDAT section \"{}\" without a global symbol",
+                            section_node.name, program.section_nodes[s].name
+                        ),
+                    }
+                }
+                // the last label at or before the value
+                Some(Base::Section(s)) if merge_labels.contains_key(&s) => {
+                    let labels = &merge_labels[&s];
+                    match labels.partition_point(|l| l.offset as i64 <= value.offset) {
+                        0 => ((s + 2) as u32, value.offset),
+                        n => (
+                            label_index(&labels[n - 1].name).unwrap(),
+                            value.offset - labels[n - 1].offset as i64,
+                        ),
+                    }
+                }
+                Some(Base::Section(s)) => ((s + 2) as u32, value.offset),
+                Some(Base::Symbol(s)) => (external_indexes[s], value.offset),
+            };
+            let addend = match fixup.kind {
+                FixupKind::Relative => offset - tail,
+                _ => offset,
+            };
+
+            let r_type = match wrt {
+                Some(wrt) => match wrt_relocation_type(wrt, fixup.kind, fixup.size, bits) {
+                    Some(r_type) => r_type,
+                    None => panic!(
+                        "{}: Invalid \"wrt\" ({:?}) in section \"{}\"",
+                        location, wrt, section_node.name
+                    ),
+                },
+                None => match relocation_type(fixup.kind, fixup.size, bits) {
+                    Some(r_type) => r_type,
+                    None => panic!(
+                        "{}: {}-byte value can't be relocated in section \"{}\"",
+                        location, fixup.size, section_node.name
+                    ),
+                },
+            };
+
+            if bits == 64 {
+                relas.extend(
+                    Elf64Rela::new(fixup.offset, r_info(symbol, r_type), addend).as_u8_slice(),
+                );
+            } else {
+                // i386 keeps the addend in the field
+                if !patch(&mut data, fixup.offset, fixup.size, fixup.kind, addend) {
+                    panic!(
+                        "{}: Value out of range in section \"{}\"",
+                        location, section_node.name
+                    );
+                }
+                relas.extend(
+                    Elf32Rel::new(fixup.offset as u32, elf32_r_info(symbol, r_type)).as_u8_slice(),
+                );
+            }
+            relocation_num += 1;
+        }
+
+        section_data.push(data);
+        if relocation_num != 0 {
+            relocations.push((i, relas));
+        }
+    }
+
+    let (header_size, section_header_size, symbol_size, relocation_size, word_size) = match bits {
+        64 => (
+            size_of::<Elf64Header>(),
+            size_of::<Elf64SectionHeader>(),
+            size_of::<Elf64SymbolTableSection>(),
+            size_of::<Elf64Rela>(),
+            8,
+        ),
+        _ => (
+            size_of::<Elf32Header>(),
+            size_of::<Elf32SectionHeader>(),
+            size_of::<Elf32SymbolTableSection>(),
+            size_of::<Elf32Rel>(),
+            4,
+        ),
+    };
+    let (relocation_type, relocation_prefix) = match bits {
+        64 => (SHT_RELA, ".rela"),
+        _ => (SHT_REL, ".rel"),
+    };
+
+    // groups + user sections + relocations + .shstrtab + .symtab + .strtab (+ .symtab_shndx)
+    let first_relocation_index = first_section_index + program.section_nodes.len();
+    let shstrtab_index = first_relocation_index + relocations.len();
+    let symtab_index = shstrtab_index + 1;
+    let strtab_index = shstrtab_index + 2;
+    let section_header_num = strtab_index + 1 + has_extended_indexes as usize;
+    let mut offset = header_size + section_header_size * section_header_num;
+
+    // names are string table ids until the table is built
+    let mut section_headers = vec![Elf64SectionHeader::default()];
+    let mut section_header_string_table = StringTable::new();
+    let mut data_bytes = Vec::new();
+
+    for (group, signature_index) in groups.iter().zip(signature_indexes) {
+        // members and their relocations
+        let mut data = GRP_COMDAT.to_le_bytes().to_vec();
+        for (i, section_node) in program.section_nodes.iter().enumerate() {
+            if section_node.attributes.group.as_ref() == Some(group) {
+                data.extend(((first_section_index + i) as u32).to_le_bytes());
+            }
+        }
+        for (n, (i, _)) in relocations.iter().enumerate() {
+            if program.section_nodes[*i].attributes.group.as_ref() == Some(group) {
+                data.extend(((first_relocation_index + n) as u32).to_le_bytes());
+            }
+        }
 
         section_headers.push(Elf64SectionHeader::new(
-            section_header_string_table.len() as u32,
-            1,
-            6,
+            section_header_string_table.add(".group"),
+            SHT_GROUP,
+            0,
             0,
-            0, // TODO
-            data_len as u64,
+            offset as u64,
+            data.len() as u64,
+            symtab_index as u32,
+            signature_index.unwrap(),
+            4,
+            4,
+        ));
+
+        align_16bytes(&mut data);
+        offset += data.len();
+        data_bytes.extend(data);
+    }
+
+    // members of a group are marked
+    let group_flag = |i: usize| match program.section_nodes[i].attributes.group {
+        Some(_) => SHF_GROUP,
+        None => 0,
+    };
+
+    for (i, (section_node, mut data)) in program.section_nodes.iter().zip(section_data).enumerate()
+    {
+        let attributes = &section_node.attributes;
+        section_headers.push(Elf64SectionHeader::new(
+            section_header_string_table.add(&section_node.name),
+            attributes.s_type,
+            attributes.flags | group_flag(i),
             0,
+            offset as u64,
+            section_node.size,
             0,
-            16,
             0,
+            attributes.align,
+            attributes.entry_size,
         ));
-        section_header_string_table.extend(format!("{}\0", section_node.name).as_bytes());
 
-        // align 16bytes
-        if data_len % 16 != 0 {
-            for _ in 0..16 - (data_len % 16) {
-                data.push(0x0);
-            }
-        }
+        align_16bytes(&mut data);
+        offset += data.len();
+        data_bytes.extend(data);
+    }
+
+    for (i, mut data) in relocations {
+        let name = format!("{}{}", relocation_prefix, program.section_nodes[i].name);
+        section_headers.push(Elf64SectionHeader::new(
+            section_header_string_table.add(&name),
+            relocation_type,
+            SHF_INFO_LINK | group_flag(i),
+            0,
+            offset as u64,
+            data.len() as u64,
+            symtab_index as u32,
+            (first_section_index + i) as u32,
+            word_size,
+            relocation_size as u64,
+        ));
 
+        align_16bytes(&mut data);
+        offset += data.len();
         data_bytes.extend(data);
     }
 
-    let mut shstrtab_section = Elf64SectionHeader::new(
-        section_header_string_table.len() as u32,
-        3,
+    let shstrtab_name = section_header_string_table.add(".shstrtab");
+    let symtab_name = section_header_string_table.add(".symtab");
+    let strtab_name = section_header_string_table.add(".strtab");
+    let symtab_shndx_name = match has_extended_indexes {
+        true => section_header_string_table.add(".symtab_shndx"),
+        false => 0,
+    };
+    let (mut section_header_string_table, section_name_offsets) =
+        section_header_string_table.build();
+
+    let shstrtab_section = Elf64SectionHeader::new(
+        shstrtab_name,
+        SHT_STRTAB,
         0,
         0,
-        0, // TODO
-        0, // TODO
+        offset as u64,
+        section_header_string_table.len() as u64,
         0,
         0,
         1,
         0,
     );
-    section_header_string_table.extend(format!(".shstrtab\0").as_bytes());
+    align_16bytes(&mut section_header_string_table);
+    offset += section_header_string_table.len();
+
+    let (mut string_table, symbol_name_offsets) = string_table.build();
+    for symbol in symbol_table.iter_mut() {
+        symbol.set_name(symbol_name_offsets[symbol.name() as usize]);
+    }
+
+    let mut _symbol_table = Vec::<u8>::new();
+    for symbol_table_section in symbol_table.iter() {
+        match bits {
+            64 => _symbol_table.extend(symbol_table_section.as_u8_slice()),
+            _ => _symbol_table
+                .extend(Elf32SymbolTableSection::from_elf64(symbol_table_section).as_u8_slice()),
+        }
+    }
 
     let symtab_section = Elf64SectionHeader::new(
-        section_header_string_table.len() as u32,
-        2,
+        symtab_name,
+        SHT_SYMTAB,
         0,
         0,
-        0, // TODO
-        0, // TODO
-        4,
-        3,
-        8,
-        24,
+        offset as u64,
+        _symbol_table.len() as u64,
+        strtab_index as u32,
+        first_global_index as u32,
+        word_size,
+        symbol_size as u64,
     );
-    section_header_string_table.extend(format!(".symtab\0").as_bytes());
+    align_16bytes(&mut _symbol_table);
+    offset += _symbol_table.len();
 
     let strtab_section = Elf64SectionHeader::new(
-        section_header_string_table.len() as u32,
-        3,
+        strtab_name,
+        SHT_STRTAB,
         0,
         0,
-        0, // TODO
-        0, // TODO
+        offset as u64,
+        string_table.len() as u64,
         0,
         0,
         1,
         0,
     );
-    section_header_string_table.extend(format!(".strtab\0").as_bytes());
+    align_16bytes(&mut string_table);
+    offset += string_table.len();
 
-    let section_header_string_table_len = section_header_string_table.len();
-    shstrtab_section.set_size(section_header_string_table_len as u64);
+    section_headers.push(shstrtab_section);
+    section_headers.push(symtab_section);
+    section_headers.push(strtab_section);
 
-    // align 16bytes
-    if section_header_string_table_len % 16 != 0 {
-        for _ in 0..16 - (section_header_string_table_len % 16) {
-            section_header_string_table.push(0x0);
+    let mut symtab_shndx = Vec::new();
+    if has_extended_indexes {
+        for index in extended_indexes {
+            symtab_shndx.extend((index as u32).to_le_bytes());
         }
+        section_headers.push(Elf64SectionHeader::new(
+            symtab_shndx_name,
+            SHT_SYMTAB_SHNDX,
+            0,
+            0,
+            offset as u64,
+            symtab_shndx.len() as u64,
+            symtab_index as u32,
+            0,
+            4,
+            4,
+        ));
     }
 
-    section_headers.push(shstrtab_section);
-    section_headers.push(symtab_section);
-    section_headers.push(strtab_section);
+    for section_header in section_headers.iter_mut() {
+        section_header.set_name(section_name_offsets[section_header.name() as usize]);
+    }
 
-    header.set_section_header_num(section_headers.len() as u16);
-    header.set_section_header_str_index(3);
+    // numbers too large for the header go in the first section header
+    let section_num = section_headers.len();
+    if section_num >= SHN_LORESERVE as usize {
+        section_headers[0].set_size(section_num as u64);
+    }
+    if shstrtab_index >= SHN_LORESERVE as usize {
+        section_headers[0].set_link(shstrtab_index as u32);
+    }
+    let header_section_num = match section_num {
+        n if n >= SHN_LORESERVE as usize => 0,
+        n => n as u16,
+    };
+    let header_str_index = symbol_section_index(shstrtab_index);
 
-    bytes.extend(header.as_u8_slice());
+    if bits == 64 {
+        let mut header = Elf64Header::template();
+        header.set_section_header_num(header_section_num);
+        header.set_section_header_str_index(header_str_index);
+        bytes.extend(header.as_u8_slice());
+    } else {
+        let mut header = Elf32Header::template();
+        header.set_section_header_num(header_section_num);
+        header.set_section_header_str_index(header_str_index);
+        bytes.extend(header.as_u8_slice());
+    }
 
     let mut _section_headers = Vec::<u8>::new();
     for section_header in section_headers.iter() {
-        _section_headers.extend(section_header.as_u8_slice());
+        match bits {
+            64 => _section_headers.extend(section_header.as_u8_slice()),
+            _ => _section_headers
+                .extend(Elf32SectionHeader::from_elf64(section_header).as_u8_slice()),
+        }
     }
     bytes.extend(_section_headers);
 
     bytes.extend(data_bytes);
     bytes.extend(section_header_string_table);
+    bytes.extend(_symbol_table);
+    bytes.extend(string_table);
+    bytes.extend(symtab_shndx);
 
-    let mut _symbol_table = Vec::<u8>::new();
-    for symbol_table_section in symbol_table.iter() {
-        _symbol_table.extend(symbol_table_section.as_u8_slice());
+    if let Some(build_id) = program.build_id {
+        let i = program
+            .section_nodes
+            .iter()
+            .position(|s| s.name == ".note.gnu.build-id")
+            .unwrap();
+        let offset = section_headers[first_section_index + i].offset();
+        fill_build_id(&mut bytes, offset as usize, build_id);
     }
-    bytes.extend(_symbol_table);
 
-    let string_table_len = string_table.len();
-    // align 16bytes
-    if string_table_len % 16 != 0 {
-        for _ in 0..16 - (string_table_len % 16) {
-            string_table.push(0x0);
-        }
+    let mut file = File::create(output_filepath).expect("Failed to create file");
+    file.write_all(&bytes).expect("Failed to write file");
+
+    file
+}
+
+fn align_16bytes(bytes: &mut Vec<u8>) {
+    let len = bytes.len();
+    if !len.is_multiple_of(16) {
+        bytes.resize(len + 16 - (len % 16), 0x0);
     }
+}
 
-    bytes.extend(string_table);
+fn symbol_attributes_mut<'a>(program: &'a mut ProgramNode, name: &str) -> &'a mut SymbolAttributes {
+    program
+        .symbol_attributes
+        .entry(name.to_string())
+        .or_insert_with(SymbolAttributes::new)
+}
 
-    // // extend instructions in text section
-    // for section_node in section_nodes.iter() {
-    //     if section_node.name.eq(".text") {
-    //         // TODO: labeled instructions
-    //         for ins in section_node.default_instructions.iter() {
-    //             text.extend(ins.mnemonic.get_opcode());
-    //         }
-    //     }
-    // }
-
-    // // .text is 16byte align
-    // let text_len = text.len();
-    // let text_offset = size_of::<Elf64Header>() + (size_of::<Elf64SectionHeader>() * 5);
-    // if text_len % 16 != 0 {
-    //     for _ in 0..16 - (text_len % 16) {
-    //         text.push(0x0);
-    //     }
-    // }
-
-    // let mut section_header_string_table = vec![0x0];
-    // section_header_string_table.extend(format!(".text\0").as_bytes());
-    // section_header_string_table.extend(format!(".shstrtab\0").as_bytes());
-    // section_header_string_table.extend(format!(".symtab\0").as_bytes());
-    // section_header_string_table.extend(format!(".strtab\0").as_bytes());
-
-    // let section_header_string_table_len = section_header_string_table.len();
-    // let section_header_string_table_offset =
-    //     header_bytes.len() + (size_of::<Elf64SectionHeader>() * 5) + text.len();
-
-    // if section_header_string_table_len % 16 != 0 {
-    //     for _ in 0..16 - (section_header_string_table_len % 16) {
-    //         section_header_string_table.push(0x0);
-    //     }
-    // }
-
-    // let null_section = Elf64SymbolTableSection::default();
-    // // type: file, ./test/test.asm
-    // let symbol_section_1 = Elf64SymbolTableSection::new(1, 4, 0, 65521, 0, 0);
-    // // type: section, .text
-    // let symbol_section_2 = Elf64SymbolTableSection::new(0, 3, 0, 1, 0, 0);
-    // // type: notype, _start
-    // let symbol_section_3 = Elf64SymbolTableSection::new(17, 16, 0, 1, 0, 0);
-
-    // let symbol_table = [
-    //     null_section.as_u8_slice(),
-    //     symbol_section_1.as_u8_slice(),
-    //     symbol_section_2.as_u8_slice(),
-    //     symbol_section_3.as_u8_slice(),
-    // ]
-    // .concat();
-
-    // let symbol_table_len = symbol_table.len();
-    // let symbol_table_offset =
-    //     section_header_string_table_offset + section_header_string_table.len();
-
-    // // string table is 16byte align
-    // let mut string_table = vec![0x0];
-    // string_table.extend(format!("{}\0", input_filepath.to_str().unwrap()).as_bytes());
-    // string_table.extend("_start\0".as_bytes());
-    // let string_table_len = string_table.len();
-    // let string_table_offset = symbol_table_offset + symbol_table_len;
-
-    // if string_table_len % 16 != 0 {
-    //     for _ in 0..16 - (string_table_len % 16) {
-    //         string_table.push(0x0);
-    //     }
-    // }
-
-    // let null_section = Elf64SectionHeader::default();
-    // let text_section =
-    //     Elf64SectionHeader::new(1, 1, 6, 0, text_offset as u64, text_len as u64, 0, 0, 16, 0);
-    // let shstrtab_section = Elf64SectionHeader::new(
-    //     7,
-    //     3,
-    //     0,
-    //     0,
-    //     section_header_string_table_offset as u64,
-    //     section_header_string_table_len as u64,
-    //     0,
-    //     0,
-    //     1,
-    //     0,
-    // );
-    // let symtab_section = Elf64SectionHeader::new(
-    //     17,
-    //     2,
-    //     0,
-    //     0,
-    //     symbol_table_offset as u64,
-    //     symbol_table_len as u64,
-    //     4,
-    //     3,
-    //     8,
-    //     24,
-    // );
-    // let strtab_section = Elf64SectionHeader::new(
-    //     25,
-    //     3,
-    //     0,
-    //     0,
-    //     string_table_offset as u64,
-    //     string_table_len as u64,
-    //     0,
-    //     0,
-    //     1,
-    //     0,
-    // );
-
-    // let mut section_headers = vec![null_section];
-
-    // section_headers.push(text_section);
-    // section_headers.push(shstrtab_section);
-    // section_headers.push(symtab_section);
-    // section_headers.push(strtab_section);
-
-    // let mut _section_headers = Vec::new();
-    // for section_header in section_headers.iter() {
-    //     _section_headers.extend(section_header.as_u8_slice());
-    // }
+// lines of the labels followed by endbr, the last of each run of labels with a global function
+// or a label whose address is taken, when an instruction other than endbr comes next
+fn endbr_lines(tokens: &[LineToken]) -> HashSet<usize> {
+    let mut globals = HashSet::from(["_start".to_string()]);
+    let mut types = HashMap::new();
+    let mut address_taken = HashSet::new();
+    // full name of each label
+    let mut names = HashMap::new();
+    let mut last_label = String::new();
 
-    let mut file = File::create(output_filepath).expect("Failed to create file");
+    for (line, token) in tokens.iter().enumerate() {
+        match token {
+            LineToken::Label(label) => {
+                let label = if label.starts_with('.') {
+                    format!("{}{}", last_label, label)
+                } else {
+                    last_label = label.clone();
+                    label.clone()
+                };
+                names.insert(line, label);
+            }
+            LineToken::Directive(
+                Directive::Global(declarations) | Directive::Weak(declarations),
+            ) => {
+                for declaration in declarations {
+                    globals.insert(declaration.name.clone());
+                    if let Some(s_type) = declaration.s_type {
+                        types.insert(declaration.name.clone(), s_type);
+                    }
+                }
+            }
+            LineToken::Directive(Directive::Type(name, s_type)) => {
+                types.insert(name.clone(), *s_type);
+            }
+            LineToken::Instruction(ins) => {
+                // direct branches go to their target without endbr
+                let is_branch = matches!(
+                    ins.mnemonic,
+                    Mnemonic::Call | Mnemonic::Jmp | Mnemonic::Jcc(_)
+                );
+                for operand in ins.operands.iter() {
+                    // addresses as values and pointers in data, memory operands only read
+                    // from the label, except for lea
+                    let exprs = match operand {
+                        Operand::Immediate(_) | Operand::SizedImmediate(..) | Operand::Far(..)
+                            if is_branch =>
+                        {
+                            continue
+                        }
+                        Operand::Immediate(expr) | Operand::SizedImmediate(_, expr) => vec![expr],
+                        Operand::Memory(MemoryOperand {
+                            disp: Some(expr), ..
+                        }) if ins.mnemonic == Mnemonic::Lea => vec![expr],
+                        Operand::Far(segment, offset) => vec![segment, offset],
+                        _ => continue,
+                    };
+                    for expr in exprs {
+                        let mut expr = expr.clone();
+                        expr.expand_local_labels(&last_label);
+                        address_taken.extend(expr.symbols().into_iter().map(str::to_string));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
 
-    // let bytes = [
-    //     header_bytes,
-    //     &_section_headers,
-    //     &text,
-    //     &section_header_string_table,
-    //     &symbol_table,
-    //     &string_table,
-    // ]
-    // .concat();
-    file.write_all(&bytes).expect("Failed to write file");
+    let is_target = |name: &String| {
+        address_taken.contains(name)
+            || globals.contains(name)
+                && matches!(
+                    types.get(name),
+                    None | Some(SymbolType::NoType | SymbolType::Function)
+                )
+    };
 
-    return file;
+    let mut lines = HashSet::new();
+    // last label of the run and whether the run has a target
+    let mut run: Option<(usize, bool)> = None;
+    for (line, token) in tokens.iter().enumerate() {
+        match token {
+            LineToken::Empty | LineToken::Comment => (),
+            LineToken::Label(_) => {
+                let marked = run.is_some_and(|(_, marked)| marked);
+                run = Some((line, marked || is_target(&names[&line])));
+            }
+            // labels of data get no endbr, it would be in the data
+            LineToken::Instruction(ins) => {
+                let is_code = ins.mnemonic.data_size().is_none()
+                    && !matches!(ins.mnemonic, Mnemonic::Endbr64 | Mnemonic::Endbr32);
+                if let Some((last, true)) = run.take() {
+                    if is_code {
+                        lines.insert(last);
+                    }
+                }
+            }
+            _ => run = None,
+        }
+    }
+
+    lines
 }
 
-fn push_global_labels(labels: &Vec<String>, section_node: &mut SectionNode) {
-    for label in labels {
-        let mut is_found = false;
-        for global_label in section_node.global_labels.iter() {
-            if label.eq(global_label) {
-                is_found = true;
-                break;
+// "bits" is the mode until a "bits" directive, "endbr" puts endbr at indirect branch targets
+pub fn gen_program(
+    tokens: &[LineToken],
+    input_filepath: &Path,
+    bits: u8,
+    endbr: bool,
+) -> ProgramNode {
+    let mut program = ProgramNode {
+        input_name: input_filepath.to_str().unwrap().to_string(),
+        section_nodes: vec![SectionNode::new(".text".to_string())],
+        labels: Vec::new(),
+        label_indexes: HashMap::new(),
+        frames: Vec::new(),
+        files: Vec::new(),
+        symbol_attributes: HashMap::new(),
+        common_symbols: Vec::new(),
+        externs: Vec::new(),
+        external_indexes: HashMap::new(),
+        origin: None,
+        build_id: None,
+    };
+    symbol_attributes_mut(&mut program, "_start").is_global = true;
+
+    let (files, locations) = source_locations(tokens);
+    program.files = files;
+
+    let mut current_section_index = 0;
+    // index in "section_nodes" by name
+    let mut section_indexes = HashMap::from([(".text".to_string(), 0)]);
+    let mut bits = bits;
+    // procedure not closed by cfi_endproc yet
+    let mut frame: Option<FrameNode> = None;
+    // last non-local label, prefix of local labels
+    let mut last_label = String::new();
+    let endbr_lines = match endbr {
+        true => endbr_lines(tokens),
+        false => HashSet::new(),
+    };
+    // where endbr was put, labels there are functions
+    let mut endbr_positions = HashSet::new();
+
+    for (line, token) in tokens.iter().enumerate() {
+        match token {
+            LineToken::Invalid(_) => unreachable!(), // have to paniced at token checker
+            LineToken::Empty => continue,
+            LineToken::Comment => continue,
+            LineToken::Instruction(ins) => {
+                let mut ins = ins.clone();
+                ins.bits = bits;
+                (ins.file, ins.line) = locations[line];
+                if encode(&ins, bits).is_none() {
+                    panic!(
+                        "{}: {:?} is not valid in {}-bit mode",
+                        location_name(&program.input_name, &program.files, locations[line]),
+                        ins.mnemonic,
+                        bits
+                    );
+                }
+
+                let position = program.section_nodes[current_section_index].size;
+                ins.for_each_expr(&mut |expr| {
+                    expr.expand_local_labels(&last_label);
+                    expr.expand_positions(current_section_index, position);
+                });
+
+                // only labels defined before can be used in the count
+                if let Some(repeat) = ins.repeat.take() {
+                    ins.times *= match repeat.eval(&|name| program.resolve(name)) {
+                        Some(value) if value.base.is_none() && value.offset >= 0 => {
+                            value.offset as u64
+                        }
+                        _ => panic!("Invalid count of times"),
+                    };
+                }
+
+                let section = &mut program.section_nodes[current_section_index];
+                if ins
+                    .checked_len()
+                    .and_then(|len| section.size.checked_add(len))
+                    .is_none()
+                {
+                    panic!(
+                        "{}: {:?} makes section \"{}\" too large",
+                        location_name(&program.input_name, &program.files, locations[line]),
+                        ins.mnemonic,
+                        section.name
+                    );
+                }
+                section.push_instruction(ins);
+            }
+            LineToken::Directive(dir) => match dir {
+                Directive::Global(declarations) | Directive::Weak(declarations) => {
+                    for declaration in declarations {
+                        let attributes = symbol_attributes_mut(&mut program, &declaration.name);
+                        attributes.apply(declaration);
+                        attributes.is_global = true;
+                        attributes.is_weak |= matches!(dir, Directive::Weak(_));
+                    }
+                }
+                Directive::Org(origin) => program.origin = Some(*origin),
+                Directive::Align(align, _) | Directive::Alignb(align) => {
+                    let section = &mut program.section_nodes[current_section_index];
+                    section.attributes.align = section.attributes.align.max(*align);
+
+                    let padding = align_up(section.size, *align) - section.size;
+                    if padding != 0 {
+                        let is_code = section.attributes.flags & SHF_EXECINSTR != 0;
+                        let mut ins = match dir {
+                            Directive::Alignb(_) => reserve(padding),
+                            _ if section.is_nobits() => reserve(padding),
+                            Directive::Align(_, Some(fill)) => data_bytes(&[*fill], padding, bits),
+                            _ if is_code => data_bytes(&nop_padding(padding, bits), 1, bits),
+                            _ => data_bytes(&[0x0], padding, bits),
+                        };
+                        (ins.file, ins.line) = locations[line];
+                        section.push_instruction(ins);
+                    }
+                }
+                Directive::Bits(b) => bits = *b,
+                // already in the source locations
+                Directive::Line(..) | Directive::File(..) | Directive::Loc(..) => (),
+                Directive::Cfi(directive) => {
+                    let offset = program.section_nodes[current_section_index].size;
+                    match (directive, &mut frame) {
+                        (CfiDirective::StartProc, None) => {
+                            frame = Some(FrameNode {
+                                section_index: current_section_index,
+                                start: offset,
+                                end: offset,
+                                directives: Vec::new(),
+                            });
+                        }
+                        (CfiDirective::StartProc, Some(_)) => {
+                            panic!("cfi_startproc inside another procedure")
+                        }
+                        (_, Some(f)) if f.section_index != current_section_index => {
+                            panic!("{:?} is not in the section of cfi_startproc", directive)
+                        }
+                        (CfiDirective::EndProc, Some(f)) => {
+                            f.end = offset;
+                            program.frames.push(frame.take().unwrap());
+                        }
+                        (_, Some(f)) => f.directives.push((offset, *directive)),
+                        (_, None) => panic!("{:?} is not after cfi_startproc", directive),
+                    }
+                }
+                Directive::Extern(names) => {
+                    program.externs.extend(names.iter().cloned());
+                }
+                Directive::Common(name, size, align) => {
+                    program
+                        .common_symbols
+                        .push((name.clone(), size.clone(), align.clone()));
+                }
+                Directive::Visibility(names, visibility) => {
+                    for name in names {
+                        symbol_attributes_mut(&mut program, name).visibility = *visibility;
+                    }
+                }
+                Directive::Type(name, s_type) => {
+                    symbol_attributes_mut(&mut program, name).s_type = *s_type;
+                }
+                Directive::Size(name, size) => {
+                    let mut size = size.clone();
+                    size.expand_local_labels(&last_label);
+                    symbol_attributes_mut(&mut program, name).size = Some(size);
+                }
+                Directive::Section(section_name, qualifiers) => {
+                    // sections can be re-entered
+                    current_section_index = match section_indexes.get(section_name) {
+                        Some(i) => *i,
+                        None => {
+                            program
+                                .section_nodes
+                                .push(SectionNode::new(section_name.clone()));
+                            let index = program.section_nodes.len() - 1;
+                            section_indexes.insert(section_name.clone(), index);
+                            index
+                        }
+                    };
+
+                    let section = &mut program.section_nodes[current_section_index];
+                    section.attributes.apply(qualifiers);
+                    // entries are pointers
+                    if section.is_pointer_array() && section.attributes.entry_size == 0 {
+                        let pointer_size = bits as u64 / 8;
+                        section.attributes.entry_size = pointer_size;
+                        section.attributes.align = section.attributes.align.max(pointer_size);
+                    }
+                }
+            },
+            LineToken::Label(label) => {
+                let label = if label.starts_with('.') {
+                    format!("{}{}", last_label, label)
+                } else {
+                    last_label = label.clone();
+                    label.clone()
+                };
+
+                if !program.push_label(LabelNode {
+                    name: label.clone(),
+                    section_index: current_section_index,
+                    offset: program.section_nodes[current_section_index].size,
+                }) {
+                    panic!(
+                        "{}: Label \"{}\" is already defined",
+                        location_name(&program.input_name, &program.files, locations[line]),
+                        label
+                    );
+                }
+
+                let section = &mut program.section_nodes[current_section_index];
+
+                if endbr_lines.contains(&line) && section.attributes.flags & SHF_EXECINSTR != 0 {
+                    endbr_positions.insert((current_section_index, section.size));
+                    section.push_instruction(Instruction {
+                        mnemonic: match bits {
+                            64 => Mnemonic::Endbr64,
+                            _ => Mnemonic::Endbr32,
+                        },
+                        operands: Vec::new(),
+                        times: 1,
+                        repeat: None,
+                        bits,
+                        line: locations[line].1,
+                        file: locations[line].0,
+                    });
+                }
             }
         }
+    }
 
-        if !is_found {
-            section_node.global_labels.push(label.clone());
+    // global symbols without a type get the function type with their endbr
+    for label in program
+        .labels
+        .iter()
+        .filter(|l| endbr_positions.contains(&(l.section_index, l.offset)))
+    {
+        if let Some(attributes) = program.symbol_attributes.get_mut(&label.name) {
+            if (attributes.is_global || attributes.is_weak)
+                && attributes.s_type == SymbolType::NoType
+            {
+                attributes.s_type = SymbolType::Function;
+            }
         }
     }
+
+    if frame.is_some() {
+        panic!("cfi_startproc without cfi_endproc");
+    }
+
+    program.index_external_symbols();
+    program
 }
 
-fn push_labeled_instructions(
-    label: &String,
-    instructions: &Vec<Instruction>,
-    section_node: &mut SectionNode,
-) {
-    if let Some((_, ins)) = section_node
-        .labeled_instructions
-        .iter_mut()
-        .find(|(l, _)| label.eq(l))
-    {
-        ins.extend(instructions.clone());
-    } else {
-        section_node
-            .labeled_instructions
-            .push((label.clone(), instructions.clone()));
+#[test]
+fn test_gen_program() {
+    let asm = "
+        section .text
+        _start:
+            nop
+        section .hoge
+        huga:
+            syscall
+        section .data
+            db 1
+        section .text
+            syscall
+        after:
+            nop
+        section .hoge
+        hoho:
+            nop
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+
+    let names: Vec<&str> = program
+        .section_nodes
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect();
+    assert_eq!(names, [".text", ".hoge", ".data"]);
+    assert_eq!(
+        program.section_nodes[0].encode().0,
+        [0x90, 0x0f, 0x05, 0x90]
+    );
+    assert_eq!(program.section_nodes[1].encode().0, [0x0f, 0x05, 0x90]);
+
+    let after = program.find_label("after").unwrap();
+    assert_eq!((after.section_index, after.offset), (0, 3));
+    let hoho = program.find_label("hoho").unwrap();
+    assert_eq!((hoho.section_index, hoho.offset), (1, 2));
+}
+
+#[test]
+fn test_times_position() {
+    let asm = "
+        org 0x7c00
+        start:
+            jmp short start
+            times 510 - ($ - $$) db 0
+            dw 0xaa55
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+
+    assert_eq!(program.origin, Some(0x7c00));
+    assert_eq!(program.section_nodes[0].size, 512);
+    assert_eq!(program.section_nodes[0].instructions[1].times, 508);
+}
+
+#[test]
+fn test_cfi_frames() {
+    let asm = "
+        nop
+        f:
+            cfi_startproc
+            push rbp
+            cfi_push rbp
+            pop rbp
+            .cfi_def_cfa_offset 8
+            ret
+            cfi_endproc
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+
+    let frame = &program.frames[0];
+    assert_eq!((frame.start, frame.end), (1, 4));
+    assert_eq!(frame.directives.len(), 2);
+    assert_eq!(frame.directives[1].0, 3);
+    assert!(matches!(
+        frame.directives[1].1,
+        CfiDirective::DefCfaOffset(8)
+    ));
+}
+
+#[test]
+fn test_source_locations() {
+    let asm = "
+        nop
+        %line 10+2 \"gen.dsl\"
+        nop
+        nop
+        .file 3 \"other.dsl\"
+        .loc 3 7
+        nop
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+
+    let locations: Vec<(usize, usize)> = program.section_nodes[0]
+        .instructions
+        .iter()
+        .map(|ins| (ins.file, ins.line))
+        .collect();
+    assert_eq!(program.files, ["gen.dsl", "other.dsl"]);
+    assert_eq!(locations, [(0, 2), (1, 10), (1, 12), (2, 7)]);
+    assert_eq!(
+        location_name(&program.input_name, &program.files, locations[2]),
+        "gen.dsl, line12"
+    );
+}
+
+#[test]
+#[should_panic(expected = "gen.dsl, line10: Label \"a\" is already defined")]
+fn test_error_location() {
+    let asm = "a:\n%line 10+1 \"gen.dsl\"\na:";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    gen_program(&tokens, Path::new("test.asm"), 64, false);
+}
+
+#[test]
+fn test_merge_sections() {
+    let asm = "
+        section .rodata.str1.1
+        a:
+            db \"abc\", 0
+        b:
+            db \"xy\", 0
+        c:
+            db \"abc\", 0
+        end:
+        section .text
+            lea rax, [rel c + 1]
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let mut program = gen_program(&tokens, Path::new("test.asm"), 64, false);
+    merge_sections(&mut program);
+
+    let section = &program.section_nodes[1];
+    assert_eq!(section.encode().0, b"abc\0xy\0");
+    assert_eq!(program.find_label("c").unwrap().offset, 0);
+    assert_eq!(program.find_label("end").unwrap().offset, 7);
+
+    // relocated against the label, the section symbol would point before the section
+    let sections = assemble(asm, "merge_sections");
+    let relocations = sections.iter().find(|s| s.name == ".rela.text").unwrap();
+    assert_eq!(relocations.data.len(), 24);
+    let word =
+        |n: usize| u64::from_le_bytes(relocations.data[n * 8..n * 8 + 8].try_into().unwrap());
+    assert_eq!(word(1) & 0xffffffff, R_X86_64_PC32 as u64);
+    let (name, info, _, _) = &test_symbols(&sections)[(word(1) >> 32) as usize];
+    assert_eq!(
+        (name.as_str(), *info),
+        ("c", st_info(STB_LOCAL, STT_NOTYPE))
+    );
+    assert_eq!(word(2) as i64, 1 - 4);
+}
+
+#[test]
+fn test_endbr() {
+    let asm = "
+        global f
+        section .text
+        f:
+        g:
+            lea rax, [rel h]
+            call i
+        h:
+            ret
+        i:
+            mov rax, [rel i]
+            lea rdi, [rel message]
+            ret
+        message:
+            db \"hi\", 0
+        pointer:
+            dq k
+        k:
+            ret
+    ";
+    let tokens: Vec<LineToken> = asm.lines().map(parse).collect();
+    let program = gen_program(&tokens, Path::new("test.asm"), 64, true);
+    let code = program.section_nodes[0].encode().0;
+
+    let offset = |name: &str| program.find_label(name).unwrap().offset;
+    let endbr = [0xf3, 0x0f, 0x1e, 0xfa];
+    assert_eq!((offset("f"), offset("g")), (0, 0));
+    assert_eq!(code[..4], endbr);
+    assert_eq!(offset("h"), 4 + 7 + 5);
+    // reading from a label doesn't take its address
+    assert_eq!(offset("i"), offset("h") + 4 + 1);
+    // the data stays as it is
+    assert_eq!(offset("message"), offset("i") + 7 + 7 + 1);
+    let message = offset("message") as usize;
+    assert_eq!(code[message..message + 3], *b"hi\0");
+    assert_eq!(offset("k"), offset("message") + 3 + 8);
+    let k = offset("k") as usize;
+    assert_eq!(code[k..k + 4], endbr);
+    assert_eq!(program.symbol_attributes("f").s_type, SymbolType::Function);
+}
+
+// section of an ELF64 file read back by tests
+#[cfg(test)]
+pub struct TestSection {
+    pub name: String,
+    pub s_type: u32,
+    pub flags: u64,
+    pub address: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub entry_size: u64,
+    pub data: Vec<u8>,
+}
+
+// sections of an object assembled by gen_elf, header 0 included
+#[cfg(test)]
+fn assemble(asm: &str, name: &str) -> Vec<TestSection> {
+    use std::{env::temp_dir, fs};
+
+    let input_filepath = temp_dir().join(format!("rasm_{}.asm", name));
+    let output_filepath = input_filepath.with_extension("o");
+    fs::write(&input_filepath, asm).unwrap();
+    gen_elf(&input_filepath, &output_filepath, 64, false, 0, false, None);
+    read_sections(&fs::read(&output_filepath).unwrap())
+}
+
+// sections of any ELF64 file, header 0 included
+#[cfg(test)]
+pub fn read_sections(bytes: &[u8]) -> Vec<TestSection> {
+    let u16_at = |o: usize| u16::from_le_bytes(bytes[o..o + 2].try_into().unwrap());
+    let u32_at = |o: usize| u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap());
+    let u64_at = |o: usize| u64::from_le_bytes(bytes[o..o + 8].try_into().unwrap());
+
+    // escaped numbers are in header 0
+    let section_header_offset = u64_at(0x28) as usize;
+    let section_num = match u16_at(0x3c) {
+        0 => u64_at(section_header_offset + 0x20) as usize,
+        n => n as usize,
+    };
+    let shstrtab_index = match u16_at(0x3e) {
+        SHN_XINDEX => u32_at(section_header_offset + 0x28) as usize,
+        n => n as usize,
+    };
+
+    let mut sections: Vec<TestSection> = (0..section_num)
+        .map(|i| {
+            let header = section_header_offset + i * size_of::<Elf64SectionHeader>();
+            let (s_type, offset, size) =
+                (u32_at(header + 4), u64_at(header + 24), u64_at(header + 32));
+            TestSection {
+                name: u32_at(header).to_string(),
+                s_type,
+                flags: u64_at(header + 8),
+                address: u64_at(header + 16),
+                size,
+                link: u32_at(header + 40),
+                info: u32_at(header + 44),
+                entry_size: u64_at(header + 56),
+                data: match (i, s_type) {
+                    (0, _) | (_, SHT_NOBITS) => Vec::new(),
+                    _ => bytes[offset as usize..(offset + size) as usize].to_vec(),
+                },
+            }
+        })
+        .collect();
+
+    let names = sections[shstrtab_index].data.clone();
+    for section in sections.iter_mut() {
+        section.name = c_string(&names, section.name.parse().unwrap());
     }
+    sections
 }
 
-fn puah_current_label_with_instructions(
-    current_label_with_instructions: &mut Option<(String, Vec<Instruction>)>,
-    current_section_node: &mut Option<SectionNode>,
-    text_section_node: &mut SectionNode,
-) {
-    if let Some((ref current_label, ref instructions)) = current_label_with_instructions {
-        if let Some(ref mut section_node) = current_section_node {
-            push_labeled_instructions(current_label, instructions, section_node);
-        } else {
-            push_labeled_instructions(current_label, instructions, text_section_node);
-        }
+#[cfg(test)]
+pub fn c_string(table: &[u8], offset: usize) -> String {
+    let end = offset + table[offset..].iter().position(|b| *b == 0x0).unwrap();
+    String::from_utf8(table[offset..end].to_vec()).unwrap()
+}
+
+// (name, st_info, st_other, st_shndx) of each symbol in .symtab
+#[cfg(test)]
+fn test_symbols(sections: &[TestSection]) -> Vec<(String, u8, u8, u16)> {
+    let symtab = sections.iter().find(|s| s.name == ".symtab").unwrap();
+    let strtab = &sections[symtab.link as usize].data;
+    symtab
+        .data
+        .chunks(size_of::<Elf64SymbolTableSection>())
+        .map(|symbol| {
+            let name = u32::from_le_bytes(symbol[..4].try_into().unwrap());
+            let index = u16::from_le_bytes(symbol[6..8].try_into().unwrap());
+            (c_string(strtab, name as usize), symbol[4], symbol[5], index)
+        })
+        .collect()
+}
+
+#[test]
+fn test_symbol_bindings() {
+    let asm = "
+        global f:function hidden
+        global d:data protected
+        weak w
+        common c 16:8
+        extern e
+        section .text
+        f:
+            call e
+            call w
+        l:
+            ret
+        section .data
+        d:
+            dq c
+    ";
+    let sections = assemble(asm, "symbol_bindings");
+    let symbols = test_symbols(&sections);
+    let symbol = |name: &str| {
+        let (_, info, other, index) = symbols.iter().find(|s| s.0 == name).unwrap();
+        (*info, *other, *index)
+    };
+    let text = sections.iter().position(|s| s.name == ".text").unwrap() as u16;
+    let data = sections.iter().position(|s| s.name == ".data").unwrap() as u16;
+
+    assert_eq!(
+        symbol("f"),
+        (st_info(STB_GLOBAL, STT_FUNC), STV_HIDDEN, text)
+    );
+    assert_eq!(
+        symbol("d"),
+        (st_info(STB_GLOBAL, STT_OBJECT), STV_PROTECTED, data)
+    );
+    assert_eq!(
+        symbol("l"),
+        (st_info(STB_LOCAL, STT_NOTYPE), STV_DEFAULT, text)
+    );
+    assert_eq!(
+        symbol("w"),
+        (st_info(STB_WEAK, STT_NOTYPE), STV_DEFAULT, SHN_UNDEF)
+    );
+    assert_eq!(
+        symbol("e"),
+        (st_info(STB_GLOBAL, STT_NOTYPE), STV_DEFAULT, SHN_UNDEF)
+    );
+    assert_eq!(
+        symbol("c"),
+        (st_info(STB_GLOBAL, STT_OBJECT), STV_DEFAULT, SHN_COMMON)
+    );
+
+    let symtab = sections.iter().find(|s| s.name == ".symtab").unwrap();
+    assert_eq!(symtab.s_type, SHT_SYMTAB);
+    assert_eq!(symtab.entry_size, 24);
+    assert_eq!(symtab.size, symtab.data.len() as u64);
+    assert_eq!(sections[text as usize].flags, SHF_ALLOC | SHF_EXECINSTR);
+
+    // common symbols keep the alignment in st_value and the size in st_size
+    let c = symbols.iter().position(|s| s.0 == "c").unwrap() * 24;
+    assert_eq!(symtab.data[c + 8], 8);
+    assert_eq!(symtab.data[c + 16], 16);
+    // locals come before the first global
+    let first_global = symbols.iter().position(|s| s.1 >> 4 != STB_LOCAL).unwrap();
+    assert_eq!(symtab.info as usize, first_global);
+}
+
+#[test]
+fn test_gnu_stack() {
+    let stack = |asm: &str, name: &str| {
+        let sections = assemble(asm, name);
+        let notes: Vec<&TestSection> = sections
+            .iter()
+            .filter(|s| s.name == ".note.GNU-stack")
+            .collect();
+        assert_eq!(notes.len(), 1);
+        (notes[0].s_type, notes[0].flags, notes[0].size)
+    };
+
+    // non-executable by default
+    assert_eq!(
+        stack("section .text\nret", "gnu_stack"),
+        (SHT_PROGBITS, 0, 0)
+    );
+    assert_eq!(
+        stack(
+            "section .note.GNU-stack noexec\nsection .text\nret",
+            "gnu_stack_noexec"
+        ),
+        (SHT_PROGBITS, 0, 0)
+    );
+    assert_eq!(
+        stack(
+            "section .note.GNU-stack exec\nsection .text\nret",
+            "gnu_stack_exec"
+        ),
+        (SHT_PROGBITS, SHF_EXECINSTR, 0)
+    );
+}
+
+#[test]
+fn test_comdat_group() {
+    let asm = "
+        global helper:function
+        extern ext
+        section .text
+            call helper
+        section .text.helper comdat=helper
+        helper:
+            call ext
+            ret
+        section .data.helper comdat=helper
+            dq ext
+    ";
+    let sections = assemble(asm, "comdat_group");
+    let index = |name: &str| sections.iter().position(|s| s.name == name).unwrap();
+    let symbols = test_symbols(&sections);
+
+    // the group comes before its members
+    let group = &sections[1];
+    assert_eq!(group.name, ".group");
+    assert_eq!(group.s_type, SHT_GROUP);
+    assert_eq!(group.entry_size, 4);
+    assert_eq!(group.link as usize, index(".symtab"));
+    assert_eq!(symbols[group.info as usize].0, "helper");
+
+    let words: Vec<u32> = group
+        .data
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let members = [
+        ".text.helper",
+        ".data.helper",
+        ".rela.text.helper",
+        ".rela.data.helper",
+    ];
+    assert_eq!(words[0], GRP_COMDAT);
+    assert_eq!(words[1..], members.map(|name| index(name) as u32));
+
+    for name in members {
+        assert_ne!(sections[index(name)].flags & SHF_GROUP, 0);
+    }
+    assert_eq!(sections[index(".text")].flags & SHF_GROUP, 0);
+    assert_eq!(sections[index(".rela.text")].flags & SHF_GROUP, 0);
+}
+
+#[test]
+fn test_wrt_relocation_type() {
+    let (relative, absolute) = (FixupKind::Relative, FixupKind::Absolute);
+    let x86_64 = [
+        ("..plt", relative, R_X86_64_PLT32),
+        ("..gottpoff", relative, R_X86_64_GOTTPOFF),
+        ("..tpoff", absolute, R_X86_64_TPOFF32),
+        ("..tpoff", FixupKind::Signed, R_X86_64_TPOFF32),
+        ("..tlsgd", relative, R_X86_64_TLSGD),
+        ("..tlsld", relative, R_X86_64_TLSLD),
+        ("..dtpoff", absolute, R_X86_64_DTPOFF32),
+    ];
+    for (wrt, kind, r_type) in x86_64 {
+        let wrt = Wrt::parse(wrt).unwrap();
+        assert_eq!(wrt_relocation_type(wrt, kind, 4, 64), Some(r_type));
+    }
+
+    let i386 = [
+        ("..plt", relative, R_386_PLT32),
+        ("..tpoff", absolute, R_386_TLS_LE),
+        ("..tlsie", absolute, R_386_TLS_IE),
+        ("..tlsgd", absolute, R_386_TLS_GD),
+        ("..tlsld", absolute, R_386_TLS_LDM),
+        ("..dtpoff", absolute, R_386_TLS_LDO_32),
+    ];
+    for (wrt, kind, r_type) in i386 {
+        let wrt = Wrt::parse(wrt).unwrap();
+        assert_eq!(wrt_relocation_type(wrt, kind, 4, 32), Some(r_type));
+    }
+
+    // no relocation for other sizes or the other kind
+    assert_eq!(wrt_relocation_type(Wrt::Plt, relative, 8, 64), None);
+    assert_eq!(wrt_relocation_type(Wrt::Plt, absolute, 4, 64), None);
+    assert_eq!(wrt_relocation_type(Wrt::GotTpOff, relative, 4, 32), None);
+}
+
+#[test]
+fn test_pointer_arrays() {
+    let asm = "
+        section .text
+        init:
+            ret
+        fini:
+            ret
+        section .init_array
+            dq init
+        section .fini_array
+            dq fini
+        section .preinit_array
+            dq init
+        section .ctors.custom init_array
+            dq fini
+    ";
+    let sections = assemble(asm, "pointer_arrays");
+    let index = |name: &str| sections.iter().position(|s| s.name == name).unwrap();
+    let symbols = test_symbols(&sections);
+
+    let arrays = [
+        (".init_array", SHT_INIT_ARRAY, 0),
+        (".fini_array", SHT_FINI_ARRAY, 1),
+        (".preinit_array", SHT_PREINIT_ARRAY, 0),
+        (".ctors.custom", SHT_INIT_ARRAY, 1),
+    ];
+    for (name, s_type, addend) in arrays {
+        let array = &sections[index(name)];
+        assert_eq!((array.s_type, array.entry_size, array.size), (s_type, 8, 8));
+
+        // one absolute pointer into .text for each entry
+        let relocations = &sections[index(&format!(".rela{}", name))];
+        assert_eq!(relocations.s_type, SHT_RELA);
+        assert_eq!(relocations.info as usize, index(name));
+        assert_eq!(relocations.data.len(), 24);
+        let word =
+            |n: usize| u64::from_le_bytes(relocations.data[n * 8..n * 8 + 8].try_into().unwrap());
+        assert_eq!(word(0), 0);
+        assert_eq!(word(1) & 0xffffffff, R_X86_64_64 as u64);
+        let (_, info, _, section) = &symbols[(word(1) >> 32) as usize];
+        assert_eq!(
+            (*info, *section as usize),
+            (st_info(STB_LOCAL, STT_SECTION), index(".text"))
+        );
+        assert_eq!(word(2), addend);
     }
+}
+
+#[test]
+fn test_extended_section_indexes() {
+    let mut asm: String = (0..SHN_LORESERVE)
+        .map(|i| format!("section .s{}\n", i))
+        .collect();
+    asm.push_str("global high\nsection .high\nlow:\nhigh:\n");
+    let sections = assemble(&asm, "extended_section_indexes");
+    let index = |name: &str| sections.iter().position(|s| s.name == name).unwrap();
+
+    // e_shnum and e_shstrndx don't fit, so they are in section header 0
+    assert_eq!(sections[0].size as usize, sections.len());
+    assert_eq!(sections[0].link as usize, index(".shstrtab"));
+
+    let symbols = test_symbols(&sections);
+    let shndx = &sections[index(".symtab_shndx")];
+    assert_eq!(shndx.s_type, SHT_SYMTAB_SHNDX);
+    assert_eq!(shndx.link as usize, index(".symtab"));
+    assert_eq!(shndx.entry_size, 4);
+    assert_eq!(shndx.data.len(), symbols.len() * 4);
 
-    *current_label_with_instructions = None;
+    // an entry for each symbol, nonzero only with SHN_XINDEX
+    let section_indexes: Vec<usize> = symbols
+        .iter()
+        .zip(shndx.data.chunks(4))
+        .map(|((_, _, _, section), entry)| {
+            let entry = u32::from_le_bytes(entry.try_into().unwrap()) as usize;
+            match *section {
+                SHN_XINDEX => entry,
+                _ => {
+                    assert_eq!(entry, 0);
+                    *section as usize
+                }
+            }
+        })
+        .collect();
+
+    let high = index(".high");
+    for (n, (name, info, _, section)) in symbols.iter().enumerate() {
+        if name == "low" || name == "high" {
+            assert_eq!((*section, section_indexes[n]), (SHN_XINDEX, high));
+        }
+        // symbols of sections from index 2, there is no group before them
+        if info & 0xf == STT_SECTION {
+            assert_eq!(section_indexes[n], n - 1);
+        }
+    }
 }
@@ -1,20 +1,506 @@
-use crate::parse::Instruction;
+use std::collections::HashMap;
+
+use crate::{
+    elf::*,
+    encode::{encode, FixupKind},
+    expr::{Base, BinaryOp, Expr, Value},
+    note::BuildId,
+    parse::*,
+};
+
+// defaults by section name, same as nasm
+const KNOWN_SECTIONS: [(&str, u32, u64, u64); 14] = [
+    (".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 16),
+    (".rodata", SHT_PROGBITS, SHF_ALLOC, 4),
+    (".lrodata", SHT_PROGBITS, SHF_ALLOC, 4),
+    (".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4),
+    (".ldata", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4),
+    (".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 4),
+    (".lbss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 4),
+    (".tdata", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 4),
+    (".tbss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 4),
+    // function pointers run at startup and exit
+    (
+        ".preinit_array",
+        SHT_PREINIT_ARRAY,
+        SHF_ALLOC | SHF_WRITE,
+        4,
+    ),
+    (".init_array", SHT_INIT_ARRAY, SHF_ALLOC | SHF_WRITE, 4),
+    (".fini_array", SHT_FINI_ARRAY, SHF_ALLOC | SHF_WRITE, 4),
+    (".comment", SHT_PROGBITS, 0, 1),
+    // non-executable stack unless "exec" is given
+    (".note.GNU-stack", SHT_PROGBITS, 0, 1),
+];
+
+// flags, entry size and alignment of ".rodata.str1.1" or ".rodata.cst16", same as gcc
+fn mergeable_layout(name: &str) -> Option<(u64, u64, u64)> {
+    let suffix = name.strip_prefix(".rodata.")?;
+    if let Some(size) = suffix.strip_prefix("cst") {
+        let size = size.parse().ok()?;
+        return Some((SHF_MERGE, size, size));
+    }
+
+    let (size, align) = suffix.strip_prefix("str")?.split_once('.')?;
+    Some((
+        SHF_MERGE | SHF_STRINGS,
+        size.parse().ok()?,
+        align.parse().ok()?,
+    ))
+}
+
+// where a section goes in bin output
+#[derive(Debug, Clone, Default)]
+pub struct BinLayout {
+    // address in the image
+    pub start: Option<u64>,
+    // address the code is linked for
+    pub vstart: Option<u64>,
+    pub follows: Option<String>,
+    pub vfollows: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionAttributes {
+    pub s_type: u32,
+    pub flags: u64,
+    pub align: u64,
+    pub entry_size: u64,
+    // section named by sh_link and the value of sh_info
+    pub link: Option<String>,
+    pub info: u32,
+    // signature of the COMDAT group
+    pub group: Option<String>,
+    pub bin_layout: BinLayout,
+}
+
+impl SectionAttributes {
+    pub fn from_name(name: &str) -> Self {
+        // ".data.foo" gets the same defaults as ".data"
+        let is_match = |known: &str| {
+            name == known || (name.starts_with(known) && name[known.len()..].starts_with('.'))
+        };
+
+        if let Some((merge_flags, entry_size, align)) = mergeable_layout(name) {
+            return Self {
+                s_type: SHT_PROGBITS,
+                flags: SHF_ALLOC | merge_flags,
+                align,
+                entry_size,
+                link: None,
+                info: 0,
+                group: None,
+                bin_layout: BinLayout::default(),
+            };
+        }
+
+        if let Some((_, s_type, flags, align)) = KNOWN_SECTIONS.iter().find(|s| is_match(s.0)) {
+            return Self {
+                s_type: *s_type,
+                flags: *flags,
+                align: *align,
+                entry_size: 0,
+                link: None,
+                info: 0,
+                group: None,
+                bin_layout: BinLayout::default(),
+            };
+        }
+
+        if is_match(".note") {
+            return Self {
+                s_type: SHT_NOTE,
+                flags: 0,
+                align: 4,
+                entry_size: 0,
+                link: None,
+                info: 0,
+                group: None,
+                bin_layout: BinLayout::default(),
+            };
+        }
+
+        Self {
+            s_type: SHT_PROGBITS,
+            flags: SHF_ALLOC,
+            align: 1,
+            entry_size: 0,
+            link: None,
+            info: 0,
+            group: None,
+            bin_layout: BinLayout::default(),
+        }
+    }
+
+    pub fn apply(&mut self, qualifiers: &[SectionQualifier]) {
+        for qualifier in qualifiers {
+            match qualifier {
+                SectionQualifier::Progbits => self.s_type = SHT_PROGBITS,
+                SectionQualifier::Nobits => self.s_type = SHT_NOBITS,
+                SectionQualifier::Note => self.s_type = SHT_NOTE,
+                SectionQualifier::PreinitArray => self.s_type = SHT_PREINIT_ARRAY,
+                SectionQualifier::InitArray => self.s_type = SHT_INIT_ARRAY,
+                SectionQualifier::FiniArray => self.s_type = SHT_FINI_ARRAY,
+                SectionQualifier::Alloc => self.flags |= SHF_ALLOC,
+                SectionQualifier::Noalloc => self.flags &= !SHF_ALLOC,
+                SectionQualifier::Exec => self.flags |= SHF_EXECINSTR,
+                SectionQualifier::Noexec => self.flags &= !SHF_EXECINSTR,
+                SectionQualifier::Write => self.flags |= SHF_WRITE,
+                SectionQualifier::Nowrite => self.flags &= !SHF_WRITE,
+                SectionQualifier::Align(align) => self.align = *align,
+                SectionQualifier::Start(start) => self.bin_layout.start = Some(*start),
+                SectionQualifier::Vstart(vstart) => self.bin_layout.vstart = Some(*vstart),
+                SectionQualifier::Follows(name) => self.bin_layout.follows = Some(name.clone()),
+                SectionQualifier::Vfollows(name) => self.bin_layout.vfollows = Some(name.clone()),
+                SectionQualifier::Merge => self.flags |= SHF_MERGE,
+                SectionQualifier::Entsize(size) => self.entry_size = *size,
+                SectionQualifier::Strings => {
+                    self.flags |= SHF_MERGE | SHF_STRINGS;
+                    if self.entry_size == 0 {
+                        self.entry_size = 1;
+                    }
+                }
+                SectionQualifier::Tls => self.flags |= SHF_TLS,
+                SectionQualifier::Comdat(name) => self.group = Some(name.clone()),
+            }
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SectionNode {
     pub name: String,
-    pub global_labels: Vec<String>,
-    pub default_instructions: Vec<Instruction>,
-    pub labeled_instructions: Vec<(String, Vec<Instruction>)>,
+    pub attributes: SectionAttributes,
+    // in source order
+    pub instructions: Vec<Instruction>,
+    // location counter
+    pub size: u64,
 }
 
 impl SectionNode {
     pub fn new(name: String) -> Self {
-        return Self {
+        Self {
+            attributes: SectionAttributes::from_name(&name),
             name,
-            global_labels: Vec::new(),
-            default_instructions: Vec::new(),
-            labeled_instructions: Vec::new(),
-        };
+            instructions: Vec::new(),
+            size: 0,
+        }
+    }
+
+    pub fn is_nobits(&self) -> bool {
+        self.attributes.s_type == SHT_NOBITS
+    }
+
+    pub fn is_pointer_array(&self) -> bool {
+        matches!(
+            self.attributes.s_type,
+            SHT_PREINIT_ARRAY | SHT_INIT_ARRAY | SHT_FINI_ARRAY
+        )
+    }
+
+    pub fn push_instruction(&mut self, ins: Instruction) {
+        if self.is_nobits() && !ins.is_zero_fill() {
+            println!(
+                "Warning: initialized data in nobits section \"{}\" is ignored",
+                self.name
+            );
+        }
+
+        self.size += ins.len();
+        self.instructions.push(ins);
+    }
+
+    // bytes and values left to resolve
+    pub fn encode(&self) -> (Vec<u8>, Vec<SectionFixup>) {
+        let mut bytes = Vec::new();
+        let mut fixups = Vec::new();
+
+        // nobits sections take no space in the file
+        if self.is_nobits() {
+            return (bytes, fixups);
+        }
+
+        for ins in self.instructions.iter() {
+            if ins.mnemonic.is_reserve() {
+                bytes.resize(bytes.len() + ins.len() as usize, 0x0);
+                continue;
+            }
+
+            let encoding = encode(ins, ins.bits).unwrap();
+            for _ in 0..ins.times {
+                let start = bytes.len() as u64;
+                bytes.extend(&encoding.bytes);
+
+                for fixup in encoding.fixups.iter() {
+                    fixups.push(SectionFixup {
+                        location: (ins.file, ins.line),
+                        is_branch: ins.is_direct_branch(),
+                        offset: start + fixup.offset as u64,
+                        end: bytes.len() as u64,
+                        size: fixup.size,
+                        kind: fixup.kind,
+                        expr: fixup.expr.clone(),
+                    });
+                }
+            }
+        }
+
+        (bytes, fixups)
+    }
+}
+
+// fixup of an instruction, placed in the section
+#[derive(Debug, Clone)]
+pub struct SectionFixup {
+    // (file, line) of the instruction
+    pub location: (usize, usize),
+    // target of a direct call or jmp
+    pub is_branch: bool,
+    pub offset: u64,
+    // end of the instruction, relative values are from here
+    pub end: u64,
+    pub size: u8,
+    pub kind: FixupKind,
+    pub expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelNode {
+    pub name: String,
+    pub section_index: usize,
+    pub offset: u64,
+}
+
+// procedure between cfi_startproc and cfi_endproc
+#[derive(Debug, Clone)]
+pub struct FrameNode {
+    pub section_index: usize,
+    pub start: u64,
+    pub end: u64,
+    // offsets in the section where the rules change
+    pub directives: Vec<(u64, CfiDirective)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgramNode {
+    // input file in diagnostics, file 0 of the source locations
+    pub input_name: String,
+    pub section_nodes: Vec<SectionNode>,
+    pub labels: Vec<LabelNode>,
+    // index in "labels" by name
+    pub label_indexes: HashMap<String, usize>,
+    pub frames: Vec<FrameNode>,
+    // source files given by "%line" and ".file", after the input file
+    pub files: Vec<String>,
+    pub symbol_attributes: HashMap<String, SymbolAttributes>,
+    // (name, size, alignment)
+    pub common_symbols: Vec<(String, Expr, Option<Expr>)>,
+    pub externs: Vec<String>,
+    // index in external_symbols() by name, set by index_external_symbols
+    pub external_indexes: HashMap<String, usize>,
+    // given by "org", only for bin output
+    pub origin: Option<u64>,
+    // .note.gnu.build-id to fill in once the output is written
+    pub build_id: Option<BuildId>,
+}
+
+impl ProgramNode {
+    // same as SectionNode::encode, but "label - $" is relative to where it's stored
+    pub fn encode_section(&self, index: usize) -> (Vec<u8>, Vec<SectionFixup>) {
+        let (bytes, mut fixups) = self.section_nodes[index].encode();
+
+        for fixup in fixups.iter_mut() {
+            let (lhs, position) = match (fixup.kind, &fixup.expr) {
+                (FixupKind::Relative, _) => continue,
+                (_, Expr::Binary(BinaryOp::Sub, lhs, rhs)) => match rhs.as_ref() {
+                    Expr::Position(section, position) if *section == index => (lhs, *position),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            // relative values are from the end of the instruction
+            fixup.expr = Expr::Binary(
+                BinaryOp::Add,
+                lhs.clone(),
+                Box::new(Expr::Number((fixup.end - position) as i64)),
+            );
+            fixup.kind = FixupKind::Relative;
+        }
+
+        (bytes, fixups)
+    }
+
+    pub fn find_label(&self, name: &str) -> Option<&LabelNode> {
+        self.label_indexes.get(name).map(|i| &self.labels[*i])
     }
+
+    // false if the name is already defined
+    pub fn push_label(&mut self, label: LabelNode) -> bool {
+        if self.label_indexes.contains_key(&label.name) {
+            return false;
+        }
+        self.label_indexes
+            .insert(label.name.clone(), self.labels.len());
+        self.labels.push(label);
+        true
+    }
+
+    pub fn symbol_attributes(&self, name: &str) -> SymbolAttributes {
+        self.symbol_attributes
+            .get(name)
+            .cloned()
+            .unwrap_or_else(SymbolAttributes::new)
+    }
+
+    // symbols referenced but not defined here, sorted by name
+    pub fn external_symbols(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .externs
+            .iter()
+            .chain(self.common_symbols.iter().map(|(name, _, _)| name))
+            .chain(
+                self.symbol_attributes
+                    .iter()
+                    .filter(|(_, attributes)| attributes.is_weak)
+                    .map(|(name, _)| name),
+            )
+            .filter(|name| self.find_label(name).is_none())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+
+        names
+    }
+
+    // once the symbols are all known, external symbols only resolve after this
+    pub fn index_external_symbols(&mut self) {
+        self.external_indexes = self
+            .external_symbols()
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name, i))
+            .collect();
+    }
+
+    // value of a label relative to its section, or of an external symbol
+    pub fn resolve(&self, name: &str) -> Option<Value> {
+        if let Some(label) = self.find_label(name) {
+            return Some(Value {
+                base: Some(Base::Section(label.section_index)),
+                offset: label.offset as i64,
+            });
+        }
+
+        self.external_indexes.get(name).map(|i| Value {
+            base: Some(Base::Symbol(*i)),
+            offset: 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolAttributes {
+    pub is_global: bool,
+    pub is_weak: bool,
+    pub s_type: SymbolType,
+    pub visibility: Visibility,
+    pub size: Option<Expr>,
+}
+
+impl SymbolAttributes {
+    pub fn new() -> Self {
+        Self {
+            is_global: false,
+            is_weak: false,
+            s_type: SymbolType::NoType,
+            visibility: Visibility::Default,
+            size: None,
+        }
+    }
+
+    pub fn apply(&mut self, declaration: &SymbolDeclaration) {
+        if let Some(s_type) = declaration.s_type {
+            self.s_type = s_type;
+        }
+
+        if let Some(visibility) = declaration.visibility {
+            self.visibility = visibility;
+        }
+
+        if let Some(size) = &declaration.size {
+            self.size = Some(size.clone());
+        }
+    }
+
+    pub fn st_bind(&self) -> u8 {
+        if self.is_weak {
+            return STB_WEAK;
+        }
+
+        if self.is_global {
+            return STB_GLOBAL;
+        }
+
+        STB_LOCAL
+    }
+
+    pub fn st_type(&self) -> u8 {
+        match self.s_type {
+            SymbolType::NoType => STT_NOTYPE,
+            SymbolType::Function => STT_FUNC,
+            SymbolType::Object => STT_OBJECT,
+            SymbolType::Tls => STT_TLS,
+        }
+    }
+
+    pub fn st_other(&self) -> u8 {
+        match self.visibility {
+            Visibility::Default => STV_DEFAULT,
+            Visibility::Internal => STV_INTERNAL,
+            Visibility::Hidden => STV_HIDDEN,
+            Visibility::Protected => STV_PROTECTED,
+        }
+    }
+}
+
+#[test]
+fn test_section_attributes() {
+    let attributes = |line: &str| match parse(line) {
+        LineToken::Directive(Directive::Section(name, qualifiers)) => {
+            let mut attributes = SectionAttributes::from_name(&name);
+            attributes.apply(&qualifiers);
+            (attributes.s_type, attributes.flags, attributes.align)
+        }
+        token => panic!("{:?}", token),
+    };
+
+    assert_eq!(
+        attributes("section .text"),
+        (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 16)
+    );
+    assert_eq!(
+        attributes("section .data.rel"),
+        (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4)
+    );
+    assert_eq!(
+        attributes("section .bss"),
+        (SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 4)
+    );
+    assert_eq!(attributes("section .note.foo"), (SHT_NOTE, 0, 4));
+    assert_eq!(attributes("section .comment"), (SHT_PROGBITS, 0, 1));
+    assert_eq!(attributes("section foo"), (SHT_PROGBITS, SHF_ALLOC, 1));
+    assert_eq!(
+        attributes("section foo nobits write align=32"),
+        (SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 32)
+    );
+    assert_eq!(
+        attributes("section .text noexec write"),
+        (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 16)
+    );
+    assert_eq!(
+        attributes("section .rodata noalloc progbits"),
+        (SHT_PROGBITS, 0, 4)
+    );
 }
@@ -0,0 +1,324 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+use crate::{
+    elf::*,
+    generator::{align_up, data_bytes},
+    node::{ProgramNode, SectionNode},
+};
+
+// xxHash64 primes
+const PRIME64: [u64; 5] = [
+    0x9e3779b185ebca87,
+    0xc2b2ae3d27d4eb4f,
+    0x165667b19e3779f9,
+    0x85ebca77c2b2ae63,
+    0x27d4eb2f165667c5,
+];
+
+// how the build ID is made, given by "--build-id=..."
+#[derive(Debug, Clone, Copy)]
+pub enum BuildId {
+    Sha1,
+    XxHash,
+    Uuid,
+}
+
+impl BuildId {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "sha1" => Some(BuildId::Sha1),
+            "xxhash" => Some(BuildId::XxHash),
+            "uuid" => Some(BuildId::Uuid),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            BuildId::Sha1 => 20,
+            BuildId::XxHash => 8,
+            BuildId::Uuid => 16,
+        }
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    // padded with 0x80, zeros and the length in bits to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    message.resize(align_up(message.len() as u64 + 8, 64) as usize - 8, 0x0);
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (a, b, c, d, e) = (t, a, b.rotate_left(30), c, d);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0x0; 20];
+    for (bytes, s) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+// XXH64 with seed 0
+fn xxhash64(data: &[u8]) -> u64 {
+    let [p1, p2, p3, p4, p5] = PRIME64;
+    let round = |acc: u64, input: u64| {
+        acc.wrapping_add(input.wrapping_mul(p2))
+            .rotate_left(31)
+            .wrapping_mul(p1)
+    };
+    let read64 = |bytes: &[u8]| u64::from_le_bytes(bytes[..8].try_into().unwrap());
+
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut lanes = [p1.wrapping_add(p2), p2, 0, p1.wrapping_neg()];
+        while rest.len() >= 32 {
+            for (i, lane) in lanes.iter_mut().enumerate() {
+                *lane = round(*lane, read64(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+
+        let mut hash = lanes[0]
+            .rotate_left(1)
+            .wrapping_add(lanes[1].rotate_left(7))
+            .wrapping_add(lanes[2].rotate_left(12))
+            .wrapping_add(lanes[3].rotate_left(18));
+        for lane in lanes {
+            hash = (hash ^ round(0, lane)).wrapping_mul(p1).wrapping_add(p4);
+        }
+        hash
+    } else {
+        p5
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= round(0, read64(rest));
+        hash = hash.rotate_left(27).wrapping_mul(p1).wrapping_add(p4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash ^= (u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64).wrapping_mul(p1);
+        hash = hash.rotate_left(23).wrapping_mul(p2).wrapping_add(p3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash ^= (*byte as u64).wrapping_mul(p5);
+        hash = hash.rotate_left(11).wrapping_mul(p1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(p2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(p3);
+    hash ^= hash >> 32;
+    hash
+}
+
+// random version 4 UUID, the hasher keys of std are random for each process
+fn uuid() -> [u8; 16] {
+    let mut uuid = [0x0; 16];
+    for half in uuid.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(time.as_nanos());
+        }
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+// note entry with the name, type and descriptor, padded to "align"
+fn note(name: &str, n_type: u32, desc: &[u8], align: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend((name.len() as u32 + 1).to_le_bytes());
+    bytes.extend((desc.len() as u32).to_le_bytes());
+    bytes.extend(n_type.to_le_bytes());
+    bytes.extend(name.as_bytes());
+    bytes.push(0x0);
+    bytes.resize(align_up(bytes.len() as u64, align) as usize, 0x0);
+    bytes.extend(desc);
+    bytes.resize(align_up(bytes.len() as u64, align) as usize, 0x0);
+    bytes
+}
+
+// allocated note section holding "bytes"
+fn note_section(name: &str, bytes: &[u8], align: u64, bits: u8) -> SectionNode {
+    let mut section = SectionNode::new(name.to_string());
+    section.attributes.s_type = SHT_NOTE;
+    section.attributes.flags = SHF_ALLOC;
+    section.attributes.align = align;
+    section.push_instruction(data_bytes(bytes, 1, bits));
+    section
+}
+
+// .note.gnu.property with the CET features given by "-z",
+// linkers AND the features of all inputs, so objects without them turn CET off
+pub fn add_property_note(program: &mut ProgramNode, bits: u8, features: u32) {
+    // nothing to mark, or already written by hand
+    if features == 0
+        || program
+            .section_nodes
+            .iter()
+            .any(|s| s.name == ".note.gnu.property")
+    {
+        return;
+    }
+
+    // the property is padded to the word size
+    let align = bits as u64 / 8;
+    let mut properties = Vec::new();
+    properties.extend(GNU_PROPERTY_X86_FEATURE_1_AND.to_le_bytes());
+    properties.extend(4u32.to_le_bytes());
+    properties.extend(features.to_le_bytes());
+    properties.resize(align_up(properties.len() as u64, align) as usize, 0x0);
+
+    let bytes = note("GNU", NT_GNU_PROPERTY_TYPE_0, &properties, align);
+    program
+        .section_nodes
+        .push(note_section(".note.gnu.property", &bytes, align, bits));
+}
+
+// .note.gnu.build-id with a zero ID, filled in by fill_build_id once the output is written
+pub fn add_build_id_note(program: &mut ProgramNode, bits: u8, build_id: BuildId) {
+    // already written by hand
+    if program
+        .section_nodes
+        .iter()
+        .any(|s| s.name == ".note.gnu.build-id")
+    {
+        return;
+    }
+
+    let bytes = note("GNU", NT_GNU_BUILD_ID, &vec![0x0; build_id.size()], 4);
+    program
+        .section_nodes
+        .push(note_section(".note.gnu.build-id", &bytes, 4, bits));
+    program.build_id = Some(build_id);
+}
+
+// hash of the whole output with the ID still zero, put in the note at "offset"
+pub fn fill_build_id(bytes: &mut [u8], offset: usize, build_id: BuildId) {
+    let id = match build_id {
+        BuildId::Sha1 => sha1(bytes).to_vec(),
+        BuildId::XxHash => xxhash64(bytes).to_le_bytes().to_vec(),
+        BuildId::Uuid => uuid().to_vec(),
+    };
+
+    // after the note header and "GNU"
+    let start = offset + 16;
+    bytes[start..start + id.len()].copy_from_slice(&id);
+}
+
+#[test]
+fn test_build_id() {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    assert_eq!(
+        hex(&sha1(b"abc")),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        hex(&sha1(&[b'a'; 64])),
+        "0098ba824b5c16427bd7a1122a5a442a25ec644d"
+    );
+    assert_eq!(xxhash64(b""), 0xef46db3751d8e999);
+    assert_eq!(xxhash64(b"abc"), 0x44bc2cf5ad770999);
+    assert_ne!(uuid(), uuid());
+    assert_eq!(uuid()[6] >> 4, 4);
+}
+
+#[test]
+fn test_property_note() {
+    use crate::{generator::gen_program, parse::parse};
+    use std::path::Path;
+
+    let note_bytes = |bits: u8, features: u32| {
+        let tokens: Vec<_> = "endbr64\nret".lines().map(parse).collect();
+        let mut program = gen_program(&tokens, Path::new("test.asm"), bits, false);
+        add_property_note(&mut program, bits, features);
+        program
+            .section_nodes
+            .iter()
+            .find(|s| s.name == ".note.gnu.property")
+            .map(|section| {
+                assert_eq!(section.attributes.align, bits as u64 / 8);
+                section.encode().0
+            })
+    };
+    let words = |words: &[u32]| -> Vec<u8> { words.iter().flat_map(|w| w.to_le_bytes()).collect() };
+
+    let features = GNU_PROPERTY_X86_FEATURE_1_IBT | GNU_PROPERTY_X86_FEATURE_1_SHSTK;
+    // "GNU", then the property padded to 8 bytes
+    let expected = words(&[
+        4,
+        16,
+        NT_GNU_PROPERTY_TYPE_0,
+        u32::from_le_bytes(*b"GNU\0"),
+        GNU_PROPERTY_X86_FEATURE_1_AND,
+        4,
+        features,
+        0,
+    ]);
+    assert_eq!(note_bytes(64, features), Some(expected));
+
+    // padded to 4 bytes
+    let expected = words(&[
+        4,
+        12,
+        NT_GNU_PROPERTY_TYPE_0,
+        u32::from_le_bytes(*b"GNU\0"),
+        GNU_PROPERTY_X86_FEATURE_1_AND,
+        4,
+        GNU_PROPERTY_X86_FEATURE_1_IBT,
+    ]);
+    assert_eq!(
+        note_bytes(32, GNU_PROPERTY_X86_FEATURE_1_IBT),
+        Some(expected)
+    );
+
+    // no note without "-z"
+    assert_eq!(note_bytes(64, 0), None);
+}
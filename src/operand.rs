@@ -0,0 +1,246 @@
+use crate::expr::{BinaryOp, Expr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterKind {
+    General,
+    // ah, ch, dh, bh
+    HighByte,
+    Segment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Register {
+    pub kind: RegisterKind,
+    pub number: u8,
+    // in bytes
+    pub size: u8,
+}
+
+const REGISTERS_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGISTERS_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGISTERS_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGISTERS_8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const REGISTERS_HIGH_BYTE: [&str; 4] = ["ah", "ch", "dh", "bh"];
+const REGISTERS_SEGMENT: [&str; 6] = ["es", "cs", "ss", "ds", "fs", "gs"];
+
+impl Register {
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let find = |names: &[&str]| names.iter().position(|n| *n == name).map(|i| i as u8);
+
+        for (names, size) in [
+            (&REGISTERS_64, 8),
+            (&REGISTERS_32, 4),
+            (&REGISTERS_16, 2),
+            (&REGISTERS_8, 1),
+        ] {
+            if let Some(number) = find(names) {
+                return Some(Self {
+                    kind: RegisterKind::General,
+                    number,
+                    size,
+                });
+            }
+        }
+
+        if let Some(number) = find(&REGISTERS_HIGH_BYTE) {
+            return Some(Self {
+                kind: RegisterKind::HighByte,
+                number: number + 4,
+                size: 1,
+            });
+        }
+
+        if let Some(number) = find(&REGISTERS_SEGMENT) {
+            return Some(Self {
+                kind: RegisterKind::Segment,
+                number,
+                size: 2,
+            });
+        }
+
+        None
+    }
+
+    pub fn is_general(&self) -> bool {
+        self.kind == RegisterKind::General
+    }
+
+    // r8-r15 need REX.R/X/B
+    pub fn is_extended(&self) -> bool {
+        self.number >= 8
+    }
+
+    // spl, bpl, sil and dil are only reachable with a REX prefix
+    pub fn needs_rex(&self) -> bool {
+        self.kind == RegisterKind::General && self.size == 1 && (4..8).contains(&self.number)
+    }
+
+    pub fn low_bits(&self) -> u8 {
+        self.number & 0x7
+    }
+}
+
+// size keyword in front of an operand
+pub fn parse_size_keyword(word: &str) -> Option<u8> {
+    match word.to_lowercase().as_str() {
+        "byte" => Some(1),
+        "word" => Some(2),
+        "dword" => Some(4),
+        "qword" => Some(8),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryOperand {
+    // in bytes, given by size keyword
+    pub size: Option<u8>,
+    pub segment: Option<Register>,
+    pub base: Option<Register>,
+    pub index: Option<(Register, u8)>,
+    pub disp: Option<Expr>,
+    // rip relative
+    pub rel: bool,
+}
+
+impl MemoryOperand {
+    // content of "[...]"
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut s = s.trim();
+        let mut memory = Self {
+            size: None,
+            segment: None,
+            base: None,
+            index: None,
+            disp: None,
+            rel: false,
+        };
+
+        if let Some((word, rest)) = s.split_once(char::is_whitespace) {
+            match word.to_lowercase().as_str() {
+                "rel" => {
+                    memory.rel = true;
+                    s = rest.trim();
+                }
+                "abs" => s = rest.trim(),
+                _ => (),
+            }
+        }
+
+        if let Some((segment, rest)) = s.split_once(':') {
+            let segment = Register::parse(segment.trim())?;
+            if segment.kind != RegisterKind::Segment {
+                return None;
+            }
+            memory.segment = Some(segment);
+            s = rest;
+        }
+
+        // registers are taken out of "wrt" and the rest goes back in
+        let (expr, wrt) = match Expr::parse(s)? {
+            Expr::Wrt(expr, wrt) => (*expr, Some(wrt)),
+            expr => (expr, None),
+        };
+        let mut terms = Vec::new();
+        split_terms(expr, false, &mut terms);
+
+        let mut disp: Option<Expr> = None;
+        for (term, negative) in terms {
+            if matches!(&term, Expr::Symbol(s) if s.eq_ignore_ascii_case("rip")) {
+                if memory.rel || negative {
+                    return None;
+                }
+                memory.rel = true;
+                continue;
+            }
+
+            match register_term(&term) {
+                Some((register, scale)) => {
+                    if negative {
+                        return None;
+                    }
+
+                    if scale.is_none() && memory.base.is_none() {
+                        memory.base = Some(register);
+                    } else if memory.index.is_none() {
+                        memory.index = Some((register, scale.unwrap_or(1)));
+                    } else {
+                        return None;
+                    }
+                }
+                None => {
+                    let op = if negative {
+                        BinaryOp::Sub
+                    } else {
+                        BinaryOp::Add
+                    };
+                    disp = Some(match disp {
+                        Some(lhs) => Expr::Binary(op, Box::new(lhs), Box::new(term)),
+                        None if negative => Expr::Neg(Box::new(term)),
+                        None => term,
+                    });
+                }
+            }
+        }
+        memory.disp = match (disp, wrt) {
+            (Some(disp), Some(wrt)) => Some(Expr::Wrt(Box::new(disp), wrt)),
+            (None, Some(_)) => return None,
+            (disp, None) => disp,
+        };
+
+        Some(memory)
+    }
+}
+
+// flatten "a + b - c" into terms with their signs
+fn split_terms(expr: Expr, negative: bool, terms: &mut Vec<(Expr, bool)>) {
+    match expr {
+        Expr::Binary(BinaryOp::Add, lhs, rhs) => {
+            split_terms(*lhs, negative, terms);
+            split_terms(*rhs, negative, terms);
+        }
+        Expr::Binary(BinaryOp::Sub, lhs, rhs) => {
+            split_terms(*lhs, negative, terms);
+            split_terms(*rhs, !negative, terms);
+        }
+        expr => terms.push((expr, negative)),
+    }
+}
+
+// "reg" or "reg*scale"
+fn register_term(term: &Expr) -> Option<(Register, Option<u8>)> {
+    let register = |e: &Expr| match e {
+        Expr::Symbol(s) => Register::parse(s).filter(|r| r.is_general()),
+        _ => None,
+    };
+
+    if let Some(r) = register(term) {
+        return Some((r, None));
+    }
+
+    if let Expr::Binary(BinaryOp::Mul, lhs, rhs) = term {
+        match (register(lhs), register(rhs), lhs.as_ref(), rhs.as_ref()) {
+            (Some(r), None, _, Expr::Number(n)) | (None, Some(r), Expr::Number(n), _)
+                if [1, 2, 4, 8].contains(n) =>
+            {
+                return Some((r, Some(*n as u8)));
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
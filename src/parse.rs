@@ -1,39 +1,578 @@
+use crate::{
+    encode::{constant, encode},
+    expr::{BinaryOp, Expr},
+    operand::*,
+};
+
 // opcodes
 const OP_SYSCALL: [u8; 2] = [0x0f, 0x05];
-//const OP_MOV_RM32_IMM32: [u8; 2] = [0x48, 0xc7];
 const OP_NOP: [u8; 1] = [0x90];
+const OP_RET: [u8; 1] = [0xc3];
+const OP_LEAVE: [u8; 1] = [0xc9];
+const OP_HLT: [u8; 1] = [0xf4];
+const OP_INT3: [u8; 1] = [0xcc];
+const OP_CPUID: [u8; 2] = [0x0f, 0xa2];
+const OP_CQO: [u8; 2] = [0x48, 0x99];
+const OP_CDQ: [u8; 1] = [0x99];
+const OP_UD2: [u8; 2] = [0x0f, 0x0b];
+const OP_CLI: [u8; 1] = [0xfa];
+const OP_STI: [u8; 1] = [0xfb];
+const OP_CLD: [u8; 1] = [0xfc];
+const OP_STD: [u8; 1] = [0xfd];
+const OP_ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+const OP_ENDBR32: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfb];
 
-#[derive(Debug, Clone)]
+// condition codes of jcc, setcc and cmovcc
+const CONDITIONS: [(&str, u8); 30] = [
+    ("o", 0x0),
+    ("no", 0x1),
+    ("b", 0x2),
+    ("c", 0x2),
+    ("nae", 0x2),
+    ("ae", 0x3),
+    ("nb", 0x3),
+    ("nc", 0x3),
+    ("e", 0x4),
+    ("z", 0x4),
+    ("ne", 0x5),
+    ("nz", 0x5),
+    ("be", 0x6),
+    ("na", 0x6),
+    ("a", 0x7),
+    ("nbe", 0x7),
+    ("s", 0x8),
+    ("ns", 0x9),
+    ("p", 0xa),
+    ("pe", 0xa),
+    ("np", 0xb),
+    ("po", 0xb),
+    ("l", 0xc),
+    ("nge", 0xc),
+    ("ge", 0xd),
+    ("nl", 0xd),
+    ("le", 0xe),
+    ("ng", 0xe),
+    ("g", 0xf),
+    ("nle", 0xf),
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Mnemonic {
     Syscall,
-    //MovRm32Imm32, // copy imm32 to rm32
     Nop,
+    Ret,
+    Leave,
+    Hlt,
+    Int,
+    Int3,
+    Cpuid,
+    Cqo,
+    Cdq,
+    Ud2,
+    Cli,
+    Sti,
+    Cld,
+    Std,
+    // indirect branch targets under CET
+    Endbr64,
+    Endbr32,
+    Mov,
+    Movzx,
+    Movsx,
+    Movsxd,
+    Lea,
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Test,
+    Inc,
+    Dec,
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+    Push,
+    Pop,
+    Call,
+    Jmp,
+    // with condition code
+    Jcc(u8),
+    Setcc(u8),
+    Cmovcc(u8),
+    // pseudo instructions
+    Db,
+    Dw,
+    Dd,
+    Dq,
+    Resb,
+    Resw,
+    Resd,
+    Resq,
 }
 
 impl Mnemonic {
+    pub fn parse(word: &str) -> Option<Self> {
+        let word = word.to_lowercase();
+        let mnemonic = match word.as_str() {
+            "syscall" => Mnemonic::Syscall,
+            "nop" => Mnemonic::Nop,
+            "ret" => Mnemonic::Ret,
+            "leave" => Mnemonic::Leave,
+            "hlt" => Mnemonic::Hlt,
+            "int" => Mnemonic::Int,
+            "int3" => Mnemonic::Int3,
+            "cpuid" => Mnemonic::Cpuid,
+            "cqo" => Mnemonic::Cqo,
+            "cdq" => Mnemonic::Cdq,
+            "ud2" => Mnemonic::Ud2,
+            "cli" => Mnemonic::Cli,
+            "sti" => Mnemonic::Sti,
+            "cld" => Mnemonic::Cld,
+            "std" => Mnemonic::Std,
+            "endbr64" => Mnemonic::Endbr64,
+            "endbr32" => Mnemonic::Endbr32,
+            "mov" => Mnemonic::Mov,
+            "movzx" => Mnemonic::Movzx,
+            "movsx" => Mnemonic::Movsx,
+            "movsxd" => Mnemonic::Movsxd,
+            "lea" => Mnemonic::Lea,
+            "add" => Mnemonic::Add,
+            "or" => Mnemonic::Or,
+            "adc" => Mnemonic::Adc,
+            "sbb" => Mnemonic::Sbb,
+            "and" => Mnemonic::And,
+            "sub" => Mnemonic::Sub,
+            "xor" => Mnemonic::Xor,
+            "cmp" => Mnemonic::Cmp,
+            "test" => Mnemonic::Test,
+            "inc" => Mnemonic::Inc,
+            "dec" => Mnemonic::Dec,
+            "not" => Mnemonic::Not,
+            "neg" => Mnemonic::Neg,
+            "mul" => Mnemonic::Mul,
+            "imul" => Mnemonic::Imul,
+            "div" => Mnemonic::Div,
+            "idiv" => Mnemonic::Idiv,
+            "rol" => Mnemonic::Rol,
+            "ror" => Mnemonic::Ror,
+            "rcl" => Mnemonic::Rcl,
+            "rcr" => Mnemonic::Rcr,
+            "shl" | "sal" => Mnemonic::Shl,
+            "shr" => Mnemonic::Shr,
+            "sar" => Mnemonic::Sar,
+            "push" => Mnemonic::Push,
+            "pop" => Mnemonic::Pop,
+            "call" => Mnemonic::Call,
+            "jmp" => Mnemonic::Jmp,
+            "db" => Mnemonic::Db,
+            "dw" => Mnemonic::Dw,
+            "dd" => Mnemonic::Dd,
+            "dq" => Mnemonic::Dq,
+            "resb" => Mnemonic::Resb,
+            "resw" => Mnemonic::Resw,
+            "resd" => Mnemonic::Resd,
+            "resq" => Mnemonic::Resq,
+            _ => {
+                let condition = |prefix: &str| {
+                    let cc = word.strip_prefix(prefix)?;
+                    CONDITIONS.iter().find(|(c, _)| *c == cc).map(|(_, n)| *n)
+                };
+
+                if let Some(cc) = condition("j") {
+                    Mnemonic::Jcc(cc)
+                } else if let Some(cc) = condition("set") {
+                    Mnemonic::Setcc(cc)
+                } else if let Some(cc) = condition("cmov") {
+                    Mnemonic::Cmovcc(cc)
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(mnemonic)
+    }
+
+    // opcode of the form without operands
     pub fn get_opcode(&self) -> Vec<u8> {
-        return match self {
+        match self {
             Mnemonic::Syscall => OP_SYSCALL.to_vec(),
             Mnemonic::Nop => OP_NOP.to_vec(),
-        };
+            Mnemonic::Ret => OP_RET.to_vec(),
+            Mnemonic::Leave => OP_LEAVE.to_vec(),
+            Mnemonic::Hlt => OP_HLT.to_vec(),
+            Mnemonic::Int3 => OP_INT3.to_vec(),
+            Mnemonic::Cpuid => OP_CPUID.to_vec(),
+            Mnemonic::Cqo => OP_CQO.to_vec(),
+            Mnemonic::Cdq => OP_CDQ.to_vec(),
+            Mnemonic::Ud2 => OP_UD2.to_vec(),
+            Mnemonic::Cli => OP_CLI.to_vec(),
+            Mnemonic::Sti => OP_STI.to_vec(),
+            Mnemonic::Cld => OP_CLD.to_vec(),
+            Mnemonic::Std => OP_STD.to_vec(),
+            Mnemonic::Endbr64 => OP_ENDBR64.to_vec(),
+            Mnemonic::Endbr32 => OP_ENDBR32.to_vec(),
+            _ => vec![],
+        }
+    }
+
+    // unit size of data/reserve pseudo instructions
+    pub fn data_size(&self) -> Option<usize> {
+        match self {
+            Mnemonic::Db | Mnemonic::Resb => Some(1),
+            Mnemonic::Dw | Mnemonic::Resw => Some(2),
+            Mnemonic::Dd | Mnemonic::Resd => Some(4),
+            Mnemonic::Dq | Mnemonic::Resq => Some(8),
+            _ => None,
+        }
+    }
+
+    pub fn is_reserve(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::Resb | Mnemonic::Resw | Mnemonic::Resd | Mnemonic::Resq
+        )
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Immediate(Expr),
+    // immediate with size keyword, like "short label"
+    SizedImmediate(u8, Expr),
+    String(Vec<u8>),
+    Register(Register),
+    Memory(MemoryOperand),
+    // "segment:offset" of far jmp and call
+    Far(Expr, Expr),
+}
+
 #[derive(Debug, Clone)]
 pub struct Instruction {
     pub mnemonic: Mnemonic,
-    pub operands: Vec<u8>,
+    pub operands: Vec<Operand>,
+    // repeat count given by "times" prefix
+    pub times: u64,
+    // "times" count not evaluated yet, it may refer to "$"
+    pub repeat: Option<Expr>,
+    // mode given by "bits"
+    pub bits: u8,
+    // line in the source file, 0 if there is none
+    pub line: usize,
+    // 0 for the input file, otherwise given by "%line" or ".file"
+    pub file: usize,
+}
+
+impl Instruction {
+    pub fn reserve_count(&self) -> Option<u64> {
+        match self.operands.first().map(|o| match o {
+            Operand::Immediate(count) => constant(count),
+            _ => None,
+        }) {
+            Some(Some(count)) if count >= 0 => Some(count as u64),
+            _ => None,
+        }
+    }
+
+    // "call label", "jmp label" or "jcc label", not through a register or memory
+    pub fn is_direct_branch(&self) -> bool {
+        matches!(
+            self.mnemonic,
+            Mnemonic::Call | Mnemonic::Jmp | Mnemonic::Jcc(_)
+        ) && matches!(
+            self.operands.first(),
+            Some(Operand::Immediate(_) | Operand::SizedImmediate(..))
+        )
+    }
+
+    // size of all repetitions, None if it doesn't fit in 64 bits
+    pub fn checked_len(&self) -> Option<u64> {
+        let len = match self.mnemonic.data_size() {
+            // reservations aren't encoded, they can be larger than memory
+            Some(size) if self.mnemonic.is_reserve() => {
+                self.reserve_count()?.checked_mul(size as u64)?
+            }
+            _ => encode(self, self.bits)?.bytes.len() as u64,
+        };
+        len.checked_mul(self.times)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.checked_len().unwrap()
+    }
+
+    // whether this can live in a nobits section without losing anything
+    pub fn is_zero_fill(&self) -> bool {
+        if self.mnemonic.is_reserve() {
+            return true;
+        }
+
+        self.mnemonic.data_size().is_some()
+            && matches!(encode(self, self.bits), Some(e) if e.fixups.is_empty() && e.bytes.iter().all(|b| *b == 0x0))
+    }
+
+    pub fn for_each_expr(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        if let Some(repeat) = &mut self.repeat {
+            f(repeat);
+        }
+
+        for operand in self.operands.iter_mut() {
+            match operand {
+                Operand::Immediate(expr) | Operand::SizedImmediate(_, expr) => f(expr),
+                Operand::Memory(MemoryOperand {
+                    disp: Some(expr), ..
+                }) => f(expr),
+                Operand::Far(segment, offset) => {
+                    f(segment);
+                    f(offset);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SectionQualifier {
+    Progbits,
+    Nobits,
+    Note,
+    PreinitArray,
+    InitArray,
+    FiniArray,
+    Alloc,
+    Noalloc,
+    Exec,
+    Noexec,
+    Write,
+    Nowrite,
+    Align(u64),
+    // layout of bin output
+    Start(u64),
+    Vstart(u64),
+    Follows(String),
+    Vfollows(String),
+    Merge,
+    Strings,
+    // size of the entries of a mergeable section
+    Entsize(u64),
+    Tls,
+    // COMDAT group with the signature symbol, only one copy is linked
+    Comdat(String),
+}
+
+impl SectionQualifier {
+    pub fn parse(word: &str) -> Option<Self> {
+        if let Some(align) = word.strip_prefix("align=") {
+            let align = parse_number(align)?;
+            if align <= 0 || !(align as u64).is_power_of_two() {
+                return None;
+            }
+
+            return Some(SectionQualifier::Align(align as u64));
+        }
+
+        if let Some((key, value)) = word.split_once('=') {
+            let address = || match parse_number(value) {
+                Some(address) if address >= 0 => Some(address as u64),
+                _ => None,
+            };
+
+            return match key {
+                "start" => Some(SectionQualifier::Start(address()?)),
+                "vstart" => Some(SectionQualifier::Vstart(address()?)),
+                "follows" if !value.is_empty() => {
+                    Some(SectionQualifier::Follows(value.to_string()))
+                }
+                "vfollows" if !value.is_empty() => {
+                    Some(SectionQualifier::Vfollows(value.to_string()))
+                }
+                "comdat" if !value.is_empty() => Some(SectionQualifier::Comdat(value.to_string())),
+                "entsize" if address()? != 0 => Some(SectionQualifier::Entsize(address()?)),
+                _ => None,
+            };
+        }
+
+        match word {
+            "progbits" => Some(SectionQualifier::Progbits),
+            "nobits" => Some(SectionQualifier::Nobits),
+            "note" => Some(SectionQualifier::Note),
+            "preinit_array" => Some(SectionQualifier::PreinitArray),
+            "init_array" => Some(SectionQualifier::InitArray),
+            "fini_array" => Some(SectionQualifier::FiniArray),
+            "alloc" => Some(SectionQualifier::Alloc),
+            "noalloc" => Some(SectionQualifier::Noalloc),
+            "exec" => Some(SectionQualifier::Exec),
+            "noexec" => Some(SectionQualifier::Noexec),
+            "write" => Some(SectionQualifier::Write),
+            "nowrite" => Some(SectionQualifier::Nowrite),
+            "merge" => Some(SectionQualifier::Merge),
+            "strings" => Some(SectionQualifier::Strings),
+            "tls" => Some(SectionQualifier::Tls),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolType {
+    NoType,
+    Function,
+    Object,
+    Tls,
+}
+
+impl SymbolType {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "notype" | "@notype" | "%notype" | "STT_NOTYPE" => Some(SymbolType::NoType),
+            "function" | "@function" | "%function" | "STT_FUNC" => Some(SymbolType::Function),
+            "data" | "object" | "@object" | "%object" | "STT_OBJECT" => Some(SymbolType::Object),
+            "tls" | "@tls_object" | "%tls_object" | "STT_TLS" => Some(SymbolType::Tls),
+            _ => None,
+        }
+    }
+}
+
+// call frame information, gas style
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CfiDirective {
+    StartProc,
+    EndProc,
+    DefCfa(Register, i64),
+    DefCfaRegister(Register),
+    DefCfaOffset(i64),
+    AdjustCfaOffset(i64),
+    // saved at the offset from the CFA
+    Offset(Register, i64),
+    Restore(Register),
+    // "push reg" and "pop reg" with the CFA based on the stack pointer
+    Push(Register),
+    Pop(Register),
+    RememberState,
+    RestoreState,
+}
+
+impl CfiDirective {
+    // "cfi_offset rbx, -16" or ".cfi_offset rbx, -16"
+    pub fn parse(name: &str, args: &str) -> Option<Self> {
+        let args: Vec<&str> = if args.is_empty() {
+            Vec::new()
+        } else {
+            args.split(',').map(|arg| arg.trim()).collect()
+        };
+        let register = |arg: &str| Register::parse(arg).filter(|r| r.is_general() && r.size >= 4);
+        let number = |arg: &str| Expr::parse(arg).as_ref().and_then(constant);
+
+        match (name.trim_start_matches('.'), &args[..]) {
+            ("cfi_startproc", []) => Some(CfiDirective::StartProc),
+            ("cfi_endproc", []) => Some(CfiDirective::EndProc),
+            ("cfi_def_cfa", [reg, offset]) => {
+                Some(CfiDirective::DefCfa(register(reg)?, number(offset)?))
+            }
+            ("cfi_def_cfa_register", [reg]) => Some(CfiDirective::DefCfaRegister(register(reg)?)),
+            ("cfi_def_cfa_offset", [offset]) => Some(CfiDirective::DefCfaOffset(number(offset)?)),
+            ("cfi_adjust_cfa_offset", [offset]) => {
+                Some(CfiDirective::AdjustCfaOffset(number(offset)?))
+            }
+            ("cfi_offset", [reg, offset]) => {
+                Some(CfiDirective::Offset(register(reg)?, number(offset)?))
+            }
+            ("cfi_restore", [reg]) => Some(CfiDirective::Restore(register(reg)?)),
+            ("cfi_push", [reg]) => Some(CfiDirective::Push(register(reg)?)),
+            ("cfi_pop", [reg]) => Some(CfiDirective::Pop(register(reg)?)),
+            ("cfi_remember_state", []) => Some(CfiDirective::RememberState),
+            ("cfi_restore_state", []) => Some(CfiDirective::RestoreState),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Default,
+    Internal,
+    Hidden,
+    Protected,
+}
+
+impl Visibility {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "default" => Some(Visibility::Default),
+            "internal" => Some(Visibility::Internal),
+            "hidden" => Some(Visibility::Hidden),
+            "protected" => Some(Visibility::Protected),
+            _ => None,
+        }
+    }
+}
+
+// "name[:type [visibility] [size]]" in global directive
+#[derive(Debug, Clone)]
+pub struct SymbolDeclaration {
+    pub name: String,
+    pub s_type: Option<SymbolType>,
+    pub visibility: Option<Visibility>,
+    pub size: Option<Expr>,
+}
+
+impl SymbolDeclaration {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            s_type: None,
+            visibility: None,
+            size: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Directive {
-    Global(Vec<String>),
-    Section(String),
+    Global(Vec<SymbolDeclaration>),
+    Weak(Vec<SymbolDeclaration>),
+    Extern(Vec<String>),
+    // name, size, alignment
+    Common(String, Expr, Option<Expr>),
+    Section(String, Vec<SectionQualifier>),
+    Visibility(Vec<String>, Visibility),
+    // gas style symbol attributes
+    Type(String, SymbolType),
+    Size(String, Expr),
+    // start address of bin output
+    Org(u64),
+    // pad to the alignment with the fill byte, nops in code or zeros
+    Align(u64, Option<u8>),
+    // reserve space up to the alignment
+    Alignb(u64),
+    Bits(u8),
+    Cfi(CfiDirective),
+    // "%line 10+1 file", the next line is line 10 of the file
+    Line(usize, usize, Option<String>),
+    // ".file 1 name", number of a file used by ".loc"
+    File(u64, String),
+    // ".loc 1 10", file number and line of what follows
+    Loc(u64, usize),
 }
 
 #[derive(Debug, Clone)]
 pub enum LineToken {
-    Invalid,
+    Invalid(CheckErrorType),
     Empty,
     Comment,
     Instruction(Instruction),
@@ -41,59 +580,476 @@ pub enum LineToken {
     Label(String),
 }
 
+// decimal, 0x-prefixed hex or h-suffixed hex
+pub fn parse_number(word: &str) -> Option<i64> {
+    let word = word.to_lowercase();
+
+    if let Some(hex) = word.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+
+    if let Some(hex) = word.strip_suffix('h') {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+
+    word.parse().ok()
+}
+
+// split operands by comma, keeping quoted strings as is
+// pieces of the text between separators outside quotes
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, c) if c == separator => {
+                pieces.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    pieces.push(&text[start..]);
+
+    pieces
+}
+
+fn split_operands(operands: &str) -> Vec<&str> {
+    split_unquoted(operands, ',')
+        .into_iter()
+        .map(str::trim)
+        .collect()
+}
+
+fn parse_operand(word: &str) -> Option<Operand> {
+    for quote in ['\'', '"', '`'] {
+        if word.len() >= 2 && word.starts_with(quote) && word.ends_with(quote) {
+            let string = word.as_bytes()[1..word.len() - 1].to_vec();
+            return Some(Operand::String(string));
+        }
+    }
+
+    // size keyword
+    let (first, rest) = split_first_word(word);
+    let size = match first.to_lowercase().as_str() {
+        "short" => Some(1),
+        "near" => None,
+        _ => parse_size_keyword(first),
+    };
+    let word = if size.is_some() || first.eq_ignore_ascii_case("near") {
+        rest
+    } else {
+        word
+    };
+
+    // "far" is only for a direct "segment:offset" here
+    let (is_far, word) = match first.eq_ignore_ascii_case("far") {
+        true => (true, rest),
+        false => (false, word),
+    };
+
+    if let (false, Some((segment, offset))) = (word.starts_with('['), word.split_once(':')) {
+        if size.is_some() {
+            return None;
+        }
+        return Some(Operand::Far(Expr::parse(segment)?, Expr::parse(offset)?));
+    }
+
+    if is_far {
+        return None;
+    }
+
+    if word.starts_with('[') && word.ends_with(']') {
+        let mut memory = MemoryOperand::parse(&word[1..word.len() - 1])?;
+        memory.size = size;
+        return Some(Operand::Memory(memory));
+    }
+
+    if let Some(register) = Register::parse(word) {
+        return Some(Operand::Register(register));
+    }
+
+    let expr = Expr::parse(word)?;
+    match size {
+        Some(size) => Some(Operand::SizedImmediate(size, expr)),
+        None => Some(Operand::Immediate(expr)),
+    }
+}
+
+fn parse_instruction(mnemonic: &str, operands: &str) -> LineToken {
+    let mnemonic = match Mnemonic::parse(mnemonic) {
+        Some(mnemonic) => mnemonic,
+        None => return LineToken::Invalid(CheckErrorType::InvalidInstruction),
+    };
+
+    let mut parsed_operands = Vec::new();
+    if !operands.is_empty() {
+        for word in split_operands(operands) {
+            match parse_operand(word) {
+                Some(operand) => parsed_operands.push(operand),
+                None => return LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+    }
+
+    let ins = Instruction {
+        mnemonic,
+        operands: parsed_operands,
+        times: 1,
+        repeat: None,
+        bits: 64,
+        line: 0,
+        file: 0,
+    };
+
+    // the mode is checked again when it's known
+    let is_encodable = || {
+        [64, 32, 16]
+            .iter()
+            .any(|bits| encode(&ins, *bits).is_some())
+    };
+    let is_valid = match ins.mnemonic {
+        Mnemonic::Resb | Mnemonic::Resw | Mnemonic::Resd | Mnemonic::Resq => {
+            ins.operands.len() == 1 && ins.reserve_count().is_some()
+        }
+        Mnemonic::Db | Mnemonic::Dw | Mnemonic::Dd | Mnemonic::Dq => {
+            !ins.operands.is_empty() && is_encodable()
+        }
+        _ => is_encodable(),
+    };
+
+    if !is_valid {
+        return LineToken::Invalid(CheckErrorType::InvalidOperand);
+    }
+
+    LineToken::Instruction(ins)
+}
+
+fn parse_symbol_declarations(symbols: &str) -> Option<Vec<SymbolDeclaration>> {
+    let mut declarations = Vec::new();
+
+    for item in symbols.split(',') {
+        let (name, spec) = match item.split_once(':') {
+            Some((name, spec)) => (name.trim(), spec.trim()),
+            None => {
+                // plain names may also be separated by spaces
+                for name in item.split_whitespace() {
+                    declarations.push(SymbolDeclaration::new(name.to_string()));
+                }
+                continue;
+            }
+        };
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let mut declaration = SymbolDeclaration::new(name.to_string());
+        let (s_type, rest) = split_first_word(spec);
+        declaration.s_type = Some(SymbolType::parse(s_type)?);
+
+        let (visibility, size) = split_first_word(rest);
+        let size = match Visibility::parse(visibility) {
+            Some(visibility) => {
+                declaration.visibility = Some(visibility);
+                size
+            }
+            None => rest,
+        };
+
+        if !size.is_empty() {
+            declaration.size = Some(Expr::parse(size)?);
+        }
+
+        declarations.push(declaration);
+    }
+
+    if declarations.is_empty() {
+        return None;
+    }
+
+    Some(declarations)
+}
+
+fn split_first_word(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (line, ""),
+    }
+}
+
 pub fn parse(line: &str) -> LineToken {
     let line = line.trim();
 
-    if line.len() == 0 {
+    if line.is_empty() {
         return LineToken::Empty;
     }
 
-    if line.chars().nth(0).unwrap() == ';' {
+    if line.starts_with(';') {
         return LineToken::Comment;
     }
 
-    // word splitted by space
-    let words: Vec<&str> = line.split(" ").collect();
+    // strip trailing comment, a ";" in a string doesn't start one
+    let line = split_unquoted(line, ';')[0].trim_end();
+
+    // primitive directive form like "[bits 32]"
+    if line.starts_with('[') && line.ends_with(']') {
+        return parse(&line[1..line.len() - 1]);
+    }
+
+    // word splitted by whitespace
+    let words: Vec<&str> = line.split_whitespace().collect();
     match words[0] {
         "global" | "section" => {
             if words.len() == 1 {
-                return LineToken::Invalid;
+                return LineToken::Invalid(CheckErrorType::InvalidInstruction);
             }
 
-            let symbols = (&words[1..])
-                .to_vec()
-                .iter()
-                .map(|w| w.to_string())
+            let directive = match words[0] {
+                "global" => match parse_symbol_declarations(split_first_word(line).1) {
+                    Some(declarations) => Directive::Global(declarations),
+                    None => return LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration),
+                },
+                "section" => {
+                    let mut qualifiers = Vec::new();
+                    for word in words[2..].iter() {
+                        match SectionQualifier::parse(word) {
+                            Some(qualifier) => qualifiers.push(qualifier),
+                            None => {
+                                return LineToken::Invalid(CheckErrorType::InvalidSectionQualifier)
+                            }
+                        }
+                    }
+
+                    Directive::Section(words[1].to_string(), qualifiers)
+                }
+                _ => unreachable!(),
+            };
+
+            LineToken::Directive(directive)
+        }
+        "weak" => match parse_symbol_declarations(split_first_word(line).1) {
+            Some(declarations) => LineToken::Directive(Directive::Weak(declarations)),
+            None => LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration),
+        },
+        "extern" => {
+            let names: Vec<String> = split_first_word(line)
+                .1
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
                 .collect();
+
+            if names.is_empty() {
+                return LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration);
+            }
+
+            LineToken::Directive(Directive::Extern(names))
+        }
+        "common" => {
+            let (_, rest) = split_first_word(line);
+            let (name, rest) = split_first_word(rest);
+            let (size, align) = match rest.split_once(':') {
+                Some((size, align)) => (size, Some(align)),
+                None => (rest, None),
+            };
+
+            let size = Expr::parse(size);
+            let align = align.map(Expr::parse);
+            match (size, align) {
+                (Some(size), None) if !name.is_empty() => {
+                    LineToken::Directive(Directive::Common(name.to_string(), size, None))
+                }
+                (Some(size), Some(Some(align))) if !name.is_empty() => {
+                    LineToken::Directive(Directive::Common(name.to_string(), size, Some(align)))
+                }
+                _ => LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration),
+            }
+        }
+        ".hidden" | ".protected" | ".internal" => {
+            let visibility = Visibility::parse(&words[0][1..]).unwrap();
+            let names: Vec<String> = split_first_word(line)
+                .1
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .collect();
+
+            if names.iter().any(|name| name.is_empty()) {
+                return LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration);
+            }
+
+            LineToken::Directive(Directive::Visibility(names, visibility))
+        }
+        ".type" | ".size" => {
+            let (_, rest) = split_first_word(line);
+            let (name, value) = match rest.split_once(',') {
+                Some((name, value)) => (name.trim().to_string(), value.trim()),
+                None => return LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration),
+            };
+
             let directive = match words[0] {
-                "global" => Directive::Global(symbols),
-                "section" => Directive::Section(symbols[0].clone()),
+                ".type" => SymbolType::parse(value).map(|t| Directive::Type(name, t)),
+                ".size" => Expr::parse(value).map(|e| Directive::Size(name, e)),
                 _ => unreachable!(),
             };
 
-            return LineToken::Directive(directive);
+            match directive {
+                Some(directive) => LineToken::Directive(directive),
+                None => LineToken::Invalid(CheckErrorType::InvalidSymbolDeclaration),
+            }
+        }
+        "times" => {
+            let (_, rest) = split_first_word(line);
+
+            // the count is an expression up to the instruction
+            let split = rest
+                .char_indices()
+                .filter(|(_, c)| c.is_whitespace())
+                .map(|(i, _)| (rest[..i].trim(), rest[i..].trim()))
+                .find(|(count, rest)| {
+                    let word = split_first_word(rest).0;
+                    (word == "times" || Mnemonic::parse(word).is_some())
+                        && Expr::parse(count).is_some()
+                });
+
+            let (count, rest) = match split {
+                Some((count, rest)) => (Expr::parse(count).unwrap(), rest),
+                None => return LineToken::Invalid(CheckErrorType::InvalidOperand),
+            };
+
+            match parse(rest) {
+                LineToken::Instruction(mut ins) => {
+                    ins.repeat = Some(match ins.repeat {
+                        // nested "times"
+                        Some(inner) => {
+                            Expr::Binary(BinaryOp::Mul, Box::new(count), Box::new(inner))
+                        }
+                        None => count,
+                    });
+                    LineToken::Instruction(ins)
+                }
+                LineToken::Invalid(error_type) => LineToken::Invalid(error_type),
+                _ => LineToken::Invalid(CheckErrorType::InvalidInstruction),
+            }
+        }
+        "bits" => match split_first_word(line).1 {
+            "16" => LineToken::Directive(Directive::Bits(16)),
+            "32" => LineToken::Directive(Directive::Bits(32)),
+            "64" => LineToken::Directive(Directive::Bits(64)),
+            _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+        },
+        "org" => {
+            match Expr::parse(split_first_word(line).1)
+                .as_ref()
+                .and_then(constant)
+            {
+                Some(origin) if origin >= 0 => LineToken::Directive(Directive::Org(origin as u64)),
+                _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+        "align" | "alignb" => {
+            let (_, rest) = split_first_word(line);
+            let (align, fill) = match rest.split_once(',') {
+                Some((align, fill)) => (align, Some(fill.trim())),
+                None => (rest, None),
+            };
+
+            let align = match Expr::parse(align).as_ref().and_then(constant) {
+                Some(align) if align > 0 && (align as u64).is_power_of_two() => align as u64,
+                _ => return LineToken::Invalid(CheckErrorType::InvalidOperand),
+            };
+            // "db 0" as in nasm or just the byte
+            let fill = fill.map(|fill| {
+                Expr::parse(fill.strip_prefix("db").unwrap_or(fill))
+                    .as_ref()
+                    .and_then(constant)
+                    .filter(|fill| (-0x80..0x100).contains(fill))
+            });
+
+            match (words[0], fill) {
+                ("align", None) => LineToken::Directive(Directive::Align(align, None)),
+                ("align", Some(Some(fill))) => {
+                    LineToken::Directive(Directive::Align(align, Some(fill as u8)))
+                }
+                ("alignb", None) => LineToken::Directive(Directive::Alignb(align)),
+                _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+        "%line" | "#line" => {
+            let (_, rest) = split_first_word(line);
+            let (position, file) = split_first_word(rest);
+            let (line_number, increment) = match position.split_once('+') {
+                Some((line_number, increment)) => (line_number, increment),
+                None => (position, "1"),
+            };
+
+            match (line_number.parse(), increment.parse()) {
+                (Ok(line_number), Ok(increment)) => LineToken::Directive(Directive::Line(
+                    line_number,
+                    increment,
+                    Some(file.trim_matches('"').to_string()).filter(|file| !file.is_empty()),
+                )),
+                _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+        ".file" => {
+            let (_, rest) = split_first_word(line);
+            // without a number it's the name of the whole file
+            let (number, name) = match split_first_word(rest) {
+                (number, name) if !number.starts_with('"') => (number.parse().ok(), name),
+                _ => (Some(0), rest),
+            };
+
+            let name = name.trim_matches('"');
+            match number {
+                Some(number) if !name.is_empty() => {
+                    LineToken::Directive(Directive::File(number, name.to_string()))
+                }
+                _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+        ".loc" => {
+            // column and options after the line are ignored
+            match (
+                words.get(1).map(|w| w.parse()),
+                words.get(2).map(|w| w.parse()),
+            ) {
+                (Some(Ok(number)), Some(Ok(line_number))) => {
+                    LineToken::Directive(Directive::Loc(number, line_number))
+                }
+                _ => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
+        }
+        w if w.trim_start_matches('.').starts_with("cfi_") => {
+            match CfiDirective::parse(w, split_first_word(line).1) {
+                Some(directive) => LineToken::Directive(Directive::Cfi(directive)),
+                None => LineToken::Invalid(CheckErrorType::InvalidOperand),
+            }
         }
         w => {
             if words.len() == 1 && w.ends_with(':') {
-                return LineToken::Label(w.replace(":", ""));
+                return LineToken::Label(w.replace(':', ""));
             }
 
             // parse instructions
-            let (mnemonic, operands) = match w {
-                "nop" => (Mnemonic::Nop, vec![]),
-                "syscall" => (Mnemonic::Syscall, vec![]),
-                _ => return LineToken::Invalid,
-            };
-
-            return LineToken::Instruction(Instruction { mnemonic, operands });
+            let (mnemonic, operands) = split_first_word(line);
+            parse_instruction(mnemonic, operands)
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum CheckErrorType {
     InvalidInstruction,
     InvalidSectionName,
+    InvalidSectionQualifier,
+    InvalidOperand,
+    InvalidSymbolDeclaration,
 }
 
 #[derive(Debug)]
@@ -105,34 +1061,62 @@ pub enum CheckResult {
     },
 }
 
-pub fn check_tokens(tokens: &Vec<LineToken>) -> CheckResult {
+pub fn check_tokens(tokens: &[LineToken]) -> CheckResult {
     for (i, token) in tokens.iter().enumerate() {
         match token {
             LineToken::Empty | LineToken::Comment => continue,
-            LineToken::Invalid => {
+            LineToken::Invalid(error_type) => {
                 return CheckResult::Error {
                     at: i,
-                    error_type: CheckErrorType::InvalidInstruction,
+                    error_type: error_type.clone(),
+                };
+            }
+            LineToken::Directive(Directive::Section(section_name, _))
+                if !section_name.starts_with('.') || section_name.len() == 1 =>
+            {
+                return CheckResult::Error {
+                    at: i,
+                    error_type: CheckErrorType::InvalidSectionName,
                 };
             }
-            LineToken::Directive(directive) => match directive {
-                Directive::Section(section_name) => {
-                    if !section_name.starts_with('.')
-                        || (section_name.starts_with('.') && section_name.len() == 1)
-                    {
-                        return CheckResult::Error {
-                            at: i,
-                            error_type: CheckErrorType::InvalidSectionName,
-                        };
-                    }
-                }
-                _ => (),
-            },
             // LineToken::Instruction { opcode, operands } => todo!(),
             // LineToken::Label(_) => todo!(),
             _ => (),
         }
     }
 
-    return CheckResult::Ok;
+    CheckResult::Ok
+}
+
+#[test]
+fn test_parse_comment() {
+    match parse("db \"a;b\", 0 ; comment") {
+        LineToken::Instruction(ins) => match ins.operands.as_slice() {
+            [Operand::String(string), Operand::Immediate(Expr::Number(0))] => {
+                assert_eq!(string, b"a;b")
+            }
+            operands => panic!("{:?}", operands),
+        },
+        token => panic!("{:?}", token),
+    }
+    assert!(matches!(parse("nop ; 'x"), LineToken::Instruction(_)));
+}
+
+#[test]
+fn test_reserve_len() {
+    let ins = |line: &str| match parse(line) {
+        LineToken::Instruction(ins) => ins,
+        token => panic!("{:?}", token),
+    };
+    assert_eq!(ins("resd 3").len(), 12);
+    let mut repeated = ins("resw 3");
+    repeated.times = 2;
+    assert_eq!(repeated.checked_len(), Some(12));
+    repeated.times = u64::MAX;
+    assert_eq!(repeated.checked_len(), None);
+    assert_eq!(
+        ins("resb 0x7fffffffffffffff").checked_len(),
+        Some(0x7fffffffffffffff)
+    );
+    assert_eq!(ins("resq 0x7fffffffffffffff").checked_len(), None);
 }
@@ -0,0 +1,740 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    mem::size_of,
+    path::Path,
+};
+
+use crate::{
+    dwarf::{add_debug_sections, add_eh_frame, add_eh_frame_hdr},
+    elf::*,
+    encode::FixupKind,
+    exec::{allocate_common_symbols, fill_eh_frame_hdr, layout_image, reserve, write_image},
+    expr::{Base, Expr, Value, Wrt},
+    generator::{
+        gen_program, link_section, location_name, merge_sections, parse_file, symbol_size,
+    },
+    node::{ProgramNode, SectionNode},
+    note::{add_build_id_note, add_property_note, BuildId},
+    parse::Visibility,
+    strtab::StringTable,
+};
+
+// "jmp [rel got_plt_entry]" and a two-byte nop
+const PLT_ENTRY_SIZE: u64 = 8;
+
+// what a dynamic relocation is applied against
+enum DynamicTarget {
+    // the load address plus a value in the image
+    Relative(Value),
+    // index of an imported symbol and the addend
+    Import(usize, i64),
+}
+
+struct DynamicRelocation {
+    section_index: usize,
+    offset: u64,
+    target: DynamicTarget,
+}
+
+// same as the System V ABI
+fn elf_hash(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0;
+    for c in name {
+        hash = (hash << 4).wrapping_add(*c as u32);
+        let high = hash & 0xf0000000;
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+        hash &= !high;
+    }
+
+    hash
+}
+
+// .hash of the symbols, the first one is the null symbol
+fn hash_table(names: &[&str]) -> Vec<u8> {
+    let bucket_num = names.len().max(1);
+    let mut buckets = vec![0u32; bucket_num];
+    let mut chains = vec![0u32; names.len()];
+
+    for (i, name) in names.iter().enumerate().skip(1) {
+        let bucket = elf_hash(name.as_bytes()) as usize % bucket_num;
+        chains[i] = buckets[bucket];
+        buckets[bucket] = i as u32;
+    }
+
+    let mut bytes = Vec::new();
+    for word in [bucket_num as u32, names.len() as u32]
+        .iter()
+        .chain(buckets.iter())
+        .chain(chains.iter())
+    {
+        bytes.extend(word.to_le_bytes());
+    }
+
+    bytes
+}
+
+// values the dynamic linker has to fill in, found before the layout is known,
+// imports called directly get PLT entries and the ones read with "wrt ..gotpcrel" GOT entries
+fn dynamic_relocations(
+    program: &ProgramNode,
+    plt_imports: &mut Vec<usize>,
+    got_imports: &mut Vec<usize>,
+) -> Vec<DynamicRelocation> {
+    let resolve = |name: &str| program.resolve(name);
+    let mut relocations = Vec::new();
+    let mut has_plt_entry = HashSet::new();
+    let mut has_got_entry = HashSet::new();
+
+    for (i, section_node) in program.section_nodes.iter().enumerate() {
+        // sections that are not loaded are not relocated either
+        if section_node.attributes.flags & SHF_ALLOC == 0 {
+            continue;
+        }
+        let (_, fixups) = program.encode_section(i);
+
+        for fixup in fixups {
+            let location = location_name(&program.input_name, &program.files, fixup.location);
+
+            // the GOT entry of an import
+            if let Expr::Wrt(expr, Wrt::GotPcRel) = &fixup.expr {
+                match (expr.as_ref(), fixup.kind, fixup.size) {
+                    (Expr::Symbol(name), FixupKind::Relative, 4) => {
+                        match program.external_indexes.get(name) {
+                            Some(import) if has_got_entry.insert(*import) => {
+                                got_imports.push(*import)
+                            }
+                            Some(_) => (),
+                            None => panic!(
+                                "{}: \"{}\" is not imported, \"wrt ..gotpcrel\" is only for imports",
+                                location, name
+                            ),
+                        }
+                    }
+                    _ => panic!(
+                        "{}: \"wrt ..gotpcrel\" needs a symbol in a rip-relative address",
+                        location
+                    ),
+                }
+                continue;
+            }
+
+            // invalid ones are reported when linking
+            let value = match fixup.expr.eval(&resolve) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let target = match (value.base, fixup.kind, fixup.size) {
+                (None, _, _) | (Some(Base::Section(_)), FixupKind::Relative, _) => continue,
+                (Some(Base::Section(_)), FixupKind::Absolute | FixupKind::Signed, 8) => {
+                    DynamicTarget::Relative(value)
+                }
+                // calls and jumps go through the PLT
+                (Some(Base::Symbol(import)), FixupKind::Relative, 4) if fixup.is_branch => {
+                    if has_plt_entry.insert(import) {
+                        plt_imports.push(import);
+                    }
+                    continue;
+                }
+                // the PLT entry is not the data, its address is in the GOT
+                (Some(Base::Symbol(_)), FixupKind::Relative, _) => panic!(
+                    "{}: Imported data has to be read through the GOT, use \"wrt ..gotpcrel\"",
+                    location
+                ),
+                (Some(Base::Symbol(import)), FixupKind::Absolute | FixupKind::Signed, 8) => {
+                    DynamicTarget::Import(import, value.offset)
+                }
+                _ => panic!(
+                    "{}: Address in section \"{}\" can't be used in a shared object, use a qword or rip-relative address",
+                    location, section_node.name
+                ),
+            };
+
+            relocations.push(DynamicRelocation {
+                section_index: i,
+                offset: fixup.offset,
+                target,
+            });
+        }
+    }
+
+    relocations
+}
+
+// section filled in after the layout, with room for the given size
+fn dynamic_section(
+    name: &str,
+    s_type: u32,
+    flags: u64,
+    align: u64,
+    entry_size: u64,
+    size: usize,
+) -> SectionNode {
+    let mut section = SectionNode::new(name.to_string());
+    section.attributes.s_type = s_type;
+    section.attributes.flags = flags;
+    section.attributes.align = align;
+    section.attributes.entry_size = entry_size;
+    section.push_instruction(reserve(size as u64));
+
+    section
+}
+
+// position-independent shared object, loaded with no other linker step,
+// "needed" are the libraries the imports come from
+#[allow(clippy::too_many_arguments)]
+pub fn gen_shared(
+    input_filepath: &Path,
+    output_filepath: &Path,
+    soname: &str,
+    needed: &[String],
+    debug: bool,
+    features: u32,
+    endbr: bool,
+    build_id: Option<BuildId>,
+) -> File {
+    let tokens = parse_file(input_filepath);
+    let mut program = gen_program(&tokens, input_filepath, 64, endbr);
+    if program.origin.is_some() {
+        panic!("\"org\" is only supported in bin format");
+    }
+    allocate_common_symbols(&mut program);
+    merge_sections(&mut program);
+    if debug {
+        add_debug_sections(&mut program, input_filepath, 8);
+    }
+    add_eh_frame(&mut program, 64);
+    add_eh_frame_hdr(&mut program);
+    add_property_note(&mut program, 64, features);
+    if let Some(build_id) = build_id {
+        add_build_id_note(&mut program, 64, build_id);
+    }
+
+    // globals with default or protected visibility are exported
+    let exports: Vec<usize> = (0..program.labels.len())
+        .filter(|i| {
+            let attributes = program.symbol_attributes(&program.labels[*i].name);
+            (attributes.is_global || attributes.is_weak)
+                && matches!(
+                    attributes.visibility,
+                    Visibility::Default | Visibility::Protected
+                )
+        })
+        .collect();
+    let imports = program.external_symbols();
+
+    let mut plt_imports = Vec::new();
+    let mut got_imports = Vec::new();
+    let relocations = dynamic_relocations(&program, &mut plt_imports, &mut got_imports);
+    let user_section_num = program.section_nodes.len();
+
+    // .dynsym has the null symbol, then exports, then imports
+    let mut symbol_names = vec![""];
+    symbol_names.extend(exports.iter().map(|i| program.labels[*i].name.as_str()));
+    symbol_names.extend(imports.iter().map(|name| name.as_str()));
+    let first_import_index = 1 + exports.len();
+
+    let mut dynamic_string_table = StringTable::new();
+    let soname_id = dynamic_string_table.add(soname);
+    let needed_ids: Vec<u32> = needed
+        .iter()
+        .map(|name| dynamic_string_table.add(name))
+        .collect();
+    let name_ids: Vec<u32> = symbol_names
+        .iter()
+        .map(|name| dynamic_string_table.add(name))
+        .collect();
+    let (dynamic_string_table, string_offsets) = dynamic_string_table.build();
+    let soname_offset = string_offsets[soname_id as usize];
+    let name_offsets: Vec<u32> = name_ids
+        .iter()
+        .map(|id| string_offsets[*id as usize])
+        .collect();
+
+    let hash = hash_table(&symbol_names);
+    // .rela.dyn has the GOT entries after the others, .rela.plt the PLT entries
+    let relocation_num = relocations.len() + got_imports.len();
+    let has_text_relocations = relocations.iter().any(|relocation| {
+        program.section_nodes[relocation.section_index]
+            .attributes
+            .flags
+            & SHF_WRITE
+            == 0
+    });
+    // constructors and destructors are run by the dynamic linker
+    if let Some(section) = program
+        .section_nodes
+        .iter()
+        .find(|s| s.attributes.s_type == SHT_PREINIT_ARRAY)
+    {
+        panic!(
+            "Section \"{}\" is not allowed in shared objects",
+            section.name
+        );
+    }
+    let pointer_arrays: Vec<(usize, i64, i64)> = [
+        (SHT_INIT_ARRAY, DT_INIT_ARRAY, DT_INIT_ARRAYSZ),
+        (SHT_FINI_ARRAY, DT_FINI_ARRAY, DT_FINI_ARRAYSZ),
+    ]
+    .into_iter()
+    .filter_map(|(s_type, tag, size_tag)| {
+        let sections: Vec<usize> = (0..program.section_nodes.len())
+            .filter(|i| program.section_nodes[*i].attributes.s_type == s_type)
+            .collect();
+        match sections[..] {
+            [] => None,
+            [i] => Some((i, tag, size_tag)),
+            _ => panic!("Only one section of type {:#x} is supported", s_type),
+        }
+    })
+    .collect();
+
+    // NEEDED, SONAME, HASH, STRTAB, SYMTAB, STRSZ, SYMENT, NULL and the arrays
+    let mut dynamic_num = needed.len() + 7 + pointer_arrays.len() * 2;
+    if relocation_num != 0 {
+        // RELA, RELASZ and RELAENT
+        dynamic_num += 3;
+    }
+    if !plt_imports.is_empty() {
+        // PLTGOT, PLTRELSZ, PLTREL, JMPREL and FLAGS
+        dynamic_num += 5;
+    }
+    if has_text_relocations {
+        dynamic_num += 1;
+    }
+
+    let symbol_size_of = size_of::<Elf64SymbolTableSection>();
+    let rela_size_of = size_of::<Elf64Rela>();
+    let dyn_size_of = size_of::<Elf64Dyn>();
+
+    let mut hash_section = dynamic_section(".hash", SHT_HASH, SHF_ALLOC, 8, 4, hash.len());
+    hash_section.attributes.link = Some(".dynsym".to_string());
+    program.section_nodes.push(hash_section);
+
+    let mut symbol_section = dynamic_section(
+        ".dynsym",
+        SHT_DYNSYM,
+        SHF_ALLOC,
+        8,
+        symbol_size_of as u64,
+        symbol_names.len() * symbol_size_of,
+    );
+    symbol_section.attributes.link = Some(".dynstr".to_string());
+    // everything but the null symbol is global
+    symbol_section.attributes.info = 1;
+    program.section_nodes.push(symbol_section);
+
+    program.section_nodes.push(dynamic_section(
+        ".dynstr",
+        SHT_STRTAB,
+        SHF_ALLOC,
+        1,
+        0,
+        dynamic_string_table.len(),
+    ));
+
+    if relocation_num != 0 {
+        let mut relocation_section = dynamic_section(
+            ".rela.dyn",
+            SHT_RELA,
+            SHF_ALLOC,
+            8,
+            rela_size_of as u64,
+            relocation_num * rela_size_of,
+        );
+        relocation_section.attributes.link = Some(".dynsym".to_string());
+        program.section_nodes.push(relocation_section);
+    }
+
+    if !plt_imports.is_empty() {
+        let mut relocation_section = dynamic_section(
+            ".rela.plt",
+            SHT_RELA,
+            SHF_ALLOC,
+            8,
+            rela_size_of as u64,
+            plt_imports.len() * rela_size_of,
+        );
+        relocation_section.attributes.link = Some(".dynsym".to_string());
+        program.section_nodes.push(relocation_section);
+
+        program.section_nodes.push(dynamic_section(
+            ".plt",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            16,
+            PLT_ENTRY_SIZE,
+            plt_imports.len() * PLT_ENTRY_SIZE as usize,
+        ));
+    }
+
+    let mut dynamic_section_node = dynamic_section(
+        ".dynamic",
+        SHT_DYNAMIC,
+        SHF_ALLOC | SHF_WRITE,
+        8,
+        dyn_size_of as u64,
+        dynamic_num * dyn_size_of,
+    );
+    dynamic_section_node.attributes.link = Some(".dynstr".to_string());
+    program.section_nodes.push(dynamic_section_node);
+
+    if !got_imports.is_empty() {
+        program.section_nodes.push(dynamic_section(
+            ".got",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            8,
+            8,
+            got_imports.len() * 8,
+        ));
+
+        // "import wrt ..gotpcrel" is the address of its GOT entry
+        let got_index = program.section_nodes.len() - 1;
+        let got_entries: HashMap<&str, usize> = got_imports
+            .iter()
+            .enumerate()
+            .map(|(entry, import)| (imports[*import].as_str(), entry))
+            .collect();
+        for section_node in program.section_nodes[..user_section_num].iter_mut() {
+            for ins in section_node.instructions.iter_mut() {
+                ins.for_each_expr(&mut |expr| {
+                    if let Expr::Wrt(symbol, Wrt::GotPcRel) = expr {
+                        if let Expr::Symbol(name) = symbol.as_ref() {
+                            let entry = got_entries[name.as_str()] as u64;
+                            *expr = Expr::Position(got_index, entry * 8);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    if !plt_imports.is_empty() {
+        program.section_nodes.push(dynamic_section(
+            ".got.plt",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            8,
+            8,
+            plt_imports.len() * 8,
+        ));
+    }
+
+    let section_index = |name: &str| program.section_nodes.iter().position(|s| s.name == name);
+
+    let mut layout = layout_image(&program, 0, 1);
+    let address_of = |name: &str| section_index(name).map(|i| layout.section_addresses[i]);
+    let plt_address = address_of(".plt").unwrap_or(0);
+    let got_address = address_of(".got").unwrap_or(0);
+    let got_plt_address = address_of(".got.plt").unwrap_or(0);
+    let mut bytes = vec![0x0; layout.size as usize];
+
+    // imports are called through their PLT entries, the rest is up to the dynamic linker
+    let plt_entries: HashMap<usize, usize> = plt_imports
+        .iter()
+        .enumerate()
+        .map(|(entry, import)| (*import, entry))
+        .collect();
+    let external_address = |name: &str| -> Option<i64> {
+        let import = program.external_indexes.get(name)?;
+        match plt_entries.get(import) {
+            Some(entry) => Some((plt_address + *entry as u64 * PLT_ENTRY_SIZE) as i64),
+            None => Some(0),
+        }
+    };
+
+    for i in 0..user_section_num {
+        let data = link_section(&program, i, &layout.section_addresses, &external_address);
+        let start = layout.section_offsets[i] as usize;
+        bytes[start..start + data.len()].copy_from_slice(&data);
+    }
+    fill_eh_frame_hdr(&program, &layout, &mut bytes);
+
+    let mut dynamic_symbols = vec![Elf64SymbolTableSection::default()];
+    for (n, i) in exports.iter().enumerate() {
+        let label = &program.labels[*i];
+        let attributes = program.symbol_attributes(&label.name);
+        dynamic_symbols.push(Elf64SymbolTableSection::new(
+            name_offsets[1 + n],
+            st_info(attributes.st_bind(), attributes.st_type()),
+            attributes.st_other(),
+            (label.section_index + 1) as u16,
+            layout.section_addresses[label.section_index] + label.offset,
+            symbol_size(&program, &label.name),
+        ));
+    }
+    for (n, name) in imports.iter().enumerate() {
+        let attributes = program.symbol_attributes(name);
+        dynamic_symbols.push(Elf64SymbolTableSection::new(
+            name_offsets[first_import_index + n],
+            st_info(
+                if attributes.is_weak {
+                    STB_WEAK
+                } else {
+                    STB_GLOBAL
+                },
+                attributes.st_type(),
+            ),
+            attributes.st_other(),
+            SHN_UNDEF,
+            0,
+            0,
+        ));
+    }
+
+    let mut relocation_entries = Vec::new();
+    for relocation in relocations.iter() {
+        let offset = layout.section_addresses[relocation.section_index] + relocation.offset;
+        relocation_entries.push(match &relocation.target {
+            DynamicTarget::Relative(value) => {
+                let base = match value.base {
+                    Some(Base::Section(s)) => layout.section_addresses[s],
+                    _ => unreachable!(),
+                };
+                Elf64Rela::new(
+                    offset,
+                    r_info(0, R_X86_64_RELATIVE),
+                    base as i64 + value.offset,
+                )
+            }
+            DynamicTarget::Import(import, addend) => Elf64Rela::new(
+                offset,
+                r_info((first_import_index + import) as u32, R_X86_64_64),
+                *addend,
+            ),
+        });
+    }
+
+    for (entry, import) in got_imports.iter().enumerate() {
+        relocation_entries.push(Elf64Rela::new(
+            got_address + entry as u64 * 8,
+            r_info((first_import_index + import) as u32, R_X86_64_GLOB_DAT),
+            0,
+        ));
+    }
+
+    // the entries are filled in at load time, so the PLT needs no resolver stub
+    let mut plt = Vec::new();
+    let mut plt_relocation_entries = Vec::new();
+    for (entry, import) in plt_imports.iter().enumerate() {
+        let got_entry = got_plt_address + entry as u64 * 8;
+        let next = plt_address + entry as u64 * PLT_ENTRY_SIZE + 6;
+        plt.extend([0xff, 0x25]);
+        plt.extend(((got_entry as i64 - next as i64) as i32).to_le_bytes());
+        plt.extend([0x66, 0x90]);
+
+        plt_relocation_entries.push(Elf64Rela::new(
+            got_entry,
+            r_info((first_import_index + import) as u32, R_X86_64_JUMP_SLOT),
+            0,
+        ));
+    }
+
+    let mut dynamic: Vec<Elf64Dyn> = needed_ids
+        .iter()
+        .map(|id| Elf64Dyn::new(DT_NEEDED, string_offsets[*id as usize] as u64))
+        .collect();
+    dynamic.extend([
+        Elf64Dyn::new(DT_SONAME, soname_offset as u64),
+        Elf64Dyn::new(DT_HASH, address_of(".hash").unwrap()),
+        Elf64Dyn::new(DT_STRTAB, address_of(".dynstr").unwrap()),
+        Elf64Dyn::new(DT_SYMTAB, address_of(".dynsym").unwrap()),
+        Elf64Dyn::new(DT_STRSZ, dynamic_string_table.len() as u64),
+        Elf64Dyn::new(DT_SYMENT, symbol_size_of as u64),
+    ]);
+    if relocation_num != 0 {
+        dynamic.push(Elf64Dyn::new(DT_RELA, address_of(".rela.dyn").unwrap()));
+        dynamic.push(Elf64Dyn::new(
+            DT_RELASZ,
+            (relocation_num * rela_size_of) as u64,
+        ));
+        dynamic.push(Elf64Dyn::new(DT_RELAENT, rela_size_of as u64));
+    }
+    if !plt_imports.is_empty() {
+        dynamic.push(Elf64Dyn::new(DT_PLTGOT, got_plt_address));
+        dynamic.push(Elf64Dyn::new(
+            DT_PLTRELSZ,
+            (plt_imports.len() * rela_size_of) as u64,
+        ));
+        dynamic.push(Elf64Dyn::new(DT_PLTREL, DT_RELA as u64));
+        dynamic.push(Elf64Dyn::new(DT_JMPREL, address_of(".rela.plt").unwrap()));
+        dynamic.push(Elf64Dyn::new(DT_FLAGS, DF_BIND_NOW));
+    }
+    for (i, tag, size_tag) in pointer_arrays {
+        dynamic.push(Elf64Dyn::new(tag, layout.section_addresses[i]));
+        dynamic.push(Elf64Dyn::new(size_tag, program.section_nodes[i].size));
+    }
+    // the dynamic linker makes the text writable while relocating
+    if has_text_relocations {
+        dynamic.push(Elf64Dyn::new(DT_TEXTREL, 0));
+    }
+    dynamic.push(Elf64Dyn::new(DT_NULL, 0));
+
+    let mut contents: Vec<(&str, Vec<u8>)> = vec![
+        (".hash", hash),
+        (".dynstr", dynamic_string_table),
+        (".plt", plt),
+        // filled in by the dynamic linker
+        (".got", vec![0x0; got_imports.len() * 8]),
+        (".got.plt", vec![0x0; plt_imports.len() * 8]),
+    ];
+    contents.push((
+        ".dynsym",
+        dynamic_symbols
+            .iter()
+            .flat_map(|s| s.as_u8_slice().to_vec())
+            .collect(),
+    ));
+    contents.push((
+        ".rela.dyn",
+        relocation_entries
+            .iter()
+            .flat_map(|r| r.as_u8_slice().to_vec())
+            .collect(),
+    ));
+    contents.push((
+        ".rela.plt",
+        plt_relocation_entries
+            .iter()
+            .flat_map(|r| r.as_u8_slice().to_vec())
+            .collect(),
+    ));
+    contents.push((
+        ".dynamic",
+        dynamic
+            .iter()
+            .flat_map(|d| d.as_u8_slice().to_vec())
+            .collect(),
+    ));
+
+    for (name, data) in contents {
+        if let Some(i) = section_index(name) {
+            let start = layout.section_offsets[i] as usize;
+            bytes[start..start + data.len()].copy_from_slice(&data);
+        }
+    }
+
+    let dynamic_index = section_index(".dynamic").unwrap();
+    let dynamic_size = program.section_nodes[dynamic_index].size;
+    layout.program_headers.push(Elf64ProgramHeader::new(
+        PT_DYNAMIC,
+        PF_R | PF_W,
+        layout.section_offsets[dynamic_index],
+        layout.section_addresses[dynamic_index],
+        layout.section_addresses[dynamic_index],
+        dynamic_size,
+        dynamic_size,
+        8,
+    ));
+
+    write_image(
+        &program,
+        input_filepath,
+        output_filepath,
+        ET_DYN,
+        0,
+        &layout,
+        bytes,
+    )
+}
+
+#[test]
+fn test_hash_table() {
+    assert_eq!(elf_hash(b""), 0);
+    assert_eq!(elf_hash(b"printf"), 0x077905a6);
+
+    // nbucket and nchain, then the buckets and chains
+    let words: Vec<u32> = hash_table(&["", "a", "b"])
+        .chunks(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        .collect();
+    assert_eq!(words[..2], [3, 3]);
+    assert_eq!(words.len(), 2 + 3 + 3);
+}
+
+#[test]
+fn test_gen_shared() {
+    use crate::generator::{c_string, read_sections};
+    use std::{env::temp_dir, fs};
+
+    let asm = "
+        extern puts, stdout
+        global hello
+        section .text
+        hello:
+            mov rax, [rel stdout wrt ..gotpcrel]
+            lea rdi, [rel message]
+            call puts
+            ret
+        section .data
+        message:
+            db \"hello\", 0
+        pointer:
+            dq message
+    ";
+    let input_filepath = temp_dir().join("rasm_gen_shared.asm");
+    let output_filepath = input_filepath.with_extension("so");
+    fs::write(&input_filepath, asm).unwrap();
+    let needed = ["libc.so.6".to_string()];
+    gen_shared(
+        &input_filepath,
+        &output_filepath,
+        "libhello.so",
+        &needed,
+        false,
+        0,
+        false,
+        None,
+    );
+    let sections = read_sections(&fs::read(&output_filepath).unwrap());
+    let section = |name: &str| sections.iter().find(|s| s.name == name).unwrap();
+    let dynstr = &section(".dynstr").data;
+
+    let symbol_names: Vec<String> = section(".dynsym")
+        .data
+        .chunks(size_of::<Elf64SymbolTableSection>())
+        .map(|s| {
+            c_string(
+                dynstr,
+                u32::from_le_bytes(s[..4].try_into().unwrap()) as usize,
+            )
+        })
+        .collect();
+    assert_eq!(symbol_names, ["", "hello", "puts", "stdout"]);
+
+    // (type, symbol name) of each entry
+    let relocations = |name: &str| -> Vec<(u32, &str)> {
+        section(name)
+            .data
+            .chunks(size_of::<Elf64Rela>())
+            .map(|r| {
+                let info = u64::from_le_bytes(r[8..16].try_into().unwrap());
+                (info as u32, symbol_names[(info >> 32) as usize].as_str())
+            })
+            .collect()
+    };
+    assert_eq!(relocations(".rela.plt"), [(R_X86_64_JUMP_SLOT, "puts")]);
+    assert_eq!(
+        relocations(".rela.dyn"),
+        [(R_X86_64_RELATIVE, ""), (R_X86_64_GLOB_DAT, "stdout")]
+    );
+    assert_eq!(section(".got").size, 8);
+    assert_eq!(section(".got.plt").size, 8);
+
+    let needed_names: Vec<String> = section(".dynamic")
+        .data
+        .chunks(size_of::<Elf64Dyn>())
+        .filter(|d| i64::from_le_bytes(d[..8].try_into().unwrap()) == DT_NEEDED)
+        .map(|d| {
+            c_string(
+                dynstr,
+                u64::from_le_bytes(d[8..].try_into().unwrap()) as usize,
+            )
+        })
+        .collect();
+    assert_eq!(needed_names, ["libc.so.6"]);
+}
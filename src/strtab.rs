@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+// names of a string table, each stored once and names ending another share its bytes
+pub struct StringTable {
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        // the empty name is id 0 and offset 0
+        Self {
+            names: vec![String::new()],
+            ids: HashMap::from([(String::new(), 0)]),
+        }
+    }
+
+    // id of the name, the offset is known once the table is built
+    pub fn add(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    // bytes of the table and the offset of each id
+    pub fn build(&self) -> (Vec<u8>, Vec<u32>) {
+        // by reversed name, so a name comes right after the longer ones it ends
+        let mut order: Vec<usize> = (1..self.names.len()).collect();
+        order.sort_by(|a, b| {
+            let reversed = |i: usize| self.names[i].bytes().rev();
+            reversed(*b).cmp(reversed(*a))
+        });
+
+        let mut bytes = vec![0x0];
+        let mut offsets = vec![0; self.names.len()];
+        // last name written and its offset
+        let mut last: (&str, usize) = ("", 0);
+
+        for i in order {
+            let name = self.names[i].as_str();
+            if last.0.ends_with(name) {
+                offsets[i] = (last.1 + last.0.len() - name.len()) as u32;
+                continue;
+            }
+
+            offsets[i] = bytes.len() as u32;
+            last = (name, bytes.len());
+            bytes.extend(name.as_bytes());
+            bytes.push(0x0);
+        }
+
+        (bytes, offsets)
+    }
+}
+
+#[test]
+fn test_string_table() {
+    let mut table = StringTable::new();
+    let text = table.add(".text");
+    let rela_text = table.add(".rela.text");
+    let data = table.add(".data");
+    assert_eq!(table.add(".text"), text);
+
+    let (bytes, offsets) = table.build();
+    assert_eq!(bytes, b"\0.rela.text\0.data\0");
+    assert_eq!(offsets[rela_text as usize], 1);
+    assert_eq!(offsets[text as usize], 6);
+    assert_eq!(offsets[data as usize], 12);
+    assert_eq!(offsets[0], 0);
+}